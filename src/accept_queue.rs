@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpResponse};
+use log::{error, warn};
+use serde::Serialize;
+
+use crate::event_result::{EventResult, Outcome};
+use crate::spool::{Backoff, Spool};
+use mh_events2pulsar::Event;
+
+/// The progress of an accepted request.
+#[derive(Serialize, Debug, Clone)]
+pub struct RequestStatus {
+    pub request_id: String,
+    /// Whether none of the events is queued anymore.
+    pub done: bool,
+    pub events: Vec<EventResult>,
+}
+
+impl RequestStatus {
+    fn new(request_id: &str, events: Vec<EventResult>) -> Self {
+        RequestStatus {
+            request_id: request_id.to_string(),
+            done: !events
+                .iter()
+                .any(|result| result.outcome == Outcome::Queued),
+            events,
+        }
+    }
+}
+
+/// Requests that are answered with `202 Accepted` and published in the
+/// background.
+///
+/// The events are spooled before the request is accepted, so they survive a
+/// restart. Their progress is kept in memory for `ttl`; after that, or after
+/// a restart, only the events that are still in the spool can be reported.
+pub struct AcceptQueue {
+    spool: Spool,
+    ttl: Duration,
+    retry_backoff: Backoff,
+    requests: Mutex<HashMap<String, (Instant, Results)>>,
+}
+
+/// The results of a request by the index of the event.
+type Results = BTreeMap<usize, EventResult>;
+
+impl AcceptQueue {
+    pub fn new(spool: Spool, ttl: Duration, retry_backoff: Backoff) -> Self {
+        AcceptQueue {
+            spool,
+            ttl,
+            retry_backoff,
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long to wait before publishing the events that failed again.
+    pub fn retry_backoff(&self) -> Backoff {
+        self.retry_backoff
+    }
+
+    /// Spool the events of a request and mark them as queued.
+    ///
+    /// Either all of the events are queued or none of them.
+    pub fn enqueue(
+        &self,
+        request_id: &str,
+        premis_events: &[Event],
+    ) -> io::Result<Vec<EventResult>> {
+        let mut results: Vec<EventResult> = Vec::new();
+        for (index, premis_event) in premis_events.iter().enumerate() {
+            let topic = premis_event.topic();
+            if let Err(e) =
+                self.spool
+                    .write_accepted(&topic, request_id, index, &premis_event.to_xml())
+            {
+                for (index, result) in results.iter().enumerate() {
+                    let _ = self.spool.remove_accepted(&result.topic, request_id, index);
+                }
+                return Err(e);
+            }
+            results.push(EventResult {
+                identifier: premis_event.identifier().to_string(),
+                event_type: premis_event.event_type.clone(),
+                topic,
+                outcome: Outcome::Queued,
+            });
+        }
+        self.insert(request_id, results.iter().cloned().enumerate().collect());
+        Ok(results)
+    }
+
+    fn insert(&self, request_id: &str, results: Results) {
+        let mut requests = self.requests.lock().unwrap();
+        requests.retain(|_, (accepted_at, _)| accepted_at.elapsed() < self.ttl);
+        requests.insert(request_id.to_string(), (Instant::now(), results));
+    }
+
+    /// Follow up on a request that was accepted before a restart, so the
+    /// progress of replaying its spooled events can be reported.
+    pub fn restore(&self, request_id: &str) -> io::Result<()> {
+        let known = self
+            .requests
+            .lock()
+            .unwrap()
+            .get(request_id)
+            .is_some_and(|(accepted_at, _)| accepted_at.elapsed() < self.ttl);
+        if !known {
+            self.insert(request_id, self.spooled_results(request_id)?);
+        }
+        Ok(())
+    }
+
+    /// Record that the event at `index` has been published, or was a
+    /// duplicate, and remove it from the spool.
+    ///
+    /// An event that failed is not finished: it stays queued and in the
+    /// spool, and is retried.
+    pub fn finish(&self, request_id: &str, index: usize, result: EventResult) {
+        if let Err(e) = self.spool.remove_accepted(&result.topic, request_id, index) {
+            error!(
+                "Could not remove event {} of request '{}' from the spool: {}",
+                index, request_id, e
+            );
+        }
+        if let Some((_, results)) = self.requests.lock().unwrap().get_mut(request_id) {
+            if let Some(event) = results.get_mut(&index) {
+                *event = result;
+            }
+        }
+    }
+
+    pub fn status(&self, request_id: &str) -> io::Result<Option<RequestStatus>> {
+        if let Some((accepted_at, results)) = self.requests.lock().unwrap().get(request_id) {
+            if accepted_at.elapsed() < self.ttl {
+                return Ok(Some(RequestStatus::new(
+                    request_id,
+                    results.values().cloned().collect(),
+                )));
+            }
+        }
+        let results = self.spooled_results(request_id)?;
+        if results.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(RequestStatus::new(
+            request_id,
+            results.into_values().collect(),
+        )))
+    }
+
+    /// The events of a request that are still in the spool, as queued.
+    fn spooled_results(&self, request_id: &str) -> io::Result<Results> {
+        let mut results = BTreeMap::new();
+        for (index, path) in self.spool.accepted(request_id)? {
+            let premis_event = Event::try_new(&fs::read_to_string(&path)?).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid event in '{}': {}", path.display(), e),
+                )
+            })?;
+            results.insert(
+                index,
+                EventResult {
+                    identifier: premis_event.identifier().to_string(),
+                    event_type: premis_event.event_type.clone(),
+                    topic: premis_event.topic(),
+                    outcome: Outcome::Queued,
+                },
+            );
+        }
+        Ok(results)
+    }
+}
+
+/// The progress of a request accepted by `/events`.
+pub async fn request_status(
+    request_id: web::Path<String>,
+    accept_queue: Option<web::Data<AcceptQueue>>,
+) -> HttpResponse {
+    let accept_queue = match accept_queue {
+        Some(accept_queue) => accept_queue,
+        None => return HttpResponse::NotFound().body("Asynchronous mode is not enabled."),
+    };
+    match accept_queue.status(&request_id) {
+        Ok(Some(status)) => HttpResponse::Ok().json(status),
+        Ok(None) => HttpResponse::NotFound().body(format!("Unknown request '{}'.", request_id)),
+        Err(e) => {
+            warn!("Could not look up request '{}': {}", request_id, e);
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    const PREMIS_EVENT: &str = r##"<premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+        <premis:eventIdentifier>
+            <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+            <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+        </premis:eventIdentifier>
+        <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+        <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+        <premis:eventOutcomeInformation>
+            <premis:eventOutcome>OK</premis:eventOutcome>
+        </premis:eventOutcomeInformation>
+        <premis:linkingAgentIdentifier>
+            <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+            <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+        </premis:linkingAgentIdentifier>
+        <premis:linkingObjectIdentifier>
+            <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+            <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+        </premis:linkingObjectIdentifier>
+    </premis:event>"##;
+
+    fn accept_queue(dir: &std::path::Path, ttl: Duration) -> AcceptQueue {
+        AcceptQueue::new(
+            Spool::new(dir.to_str().unwrap()).unwrap(),
+            ttl,
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(10)),
+        )
+    }
+
+    #[test]
+    fn test_enqueue_and_finish() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let accept_queue = accept_queue(&dir, Duration::from_secs(3600));
+        let premis_events = vec![Event::new(PREMIS_EVENT), Event::new(PREMIS_EVENT)];
+        // Act
+        let queued = accept_queue.enqueue("abc", &premis_events).unwrap();
+        let mut published = queued[0].clone();
+        published.outcome = Outcome::Published {
+            message_id: Some(String::from("42:7:-1")),
+        };
+        accept_queue.finish("abc", 0, published.clone());
+        // Assert
+        let status = accept_queue.status("abc").unwrap().unwrap();
+        assert!(!status.done);
+        assert_eq!(status.events[0].outcome, published.outcome);
+        assert_eq!(status.events[1].outcome, Outcome::Queued);
+        assert_eq!(accept_queue.spool.accepted("abc").unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_status_from_spool() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        accept_queue(&dir, Duration::from_secs(3600))
+            .enqueue("abc", &[Event::new(PREMIS_EVENT)])
+            .unwrap();
+        // A restart loses the requests in memory.
+        let accept_queue = accept_queue(&dir, Duration::from_secs(3600));
+        // Act
+        let status = accept_queue.status("abc").unwrap().unwrap();
+        // Assert
+        assert_eq!(status.events.len(), 1);
+        assert_eq!(status.events[0].identifier, "111");
+        assert_eq!(status.events[0].outcome, Outcome::Queued);
+        assert!(accept_queue.status("def").unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_status_invalid_spooled_event() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let accept_queue = accept_queue(&dir, Duration::from_secs(3600));
+        accept_queue
+            .spool
+            .write_accepted("be.mediahaven.flow.archived", "abc", 0, "<premis:event")
+            .unwrap();
+        // Act
+        let status = accept_queue.status("abc");
+        // Assert
+        let error = status.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("Invalid event"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let premis_events = vec![Event::new(PREMIS_EVENT), Event::new(PREMIS_EVENT)];
+        let queued = accept_queue(&dir, Duration::from_secs(3600))
+            .enqueue("abc", &premis_events)
+            .unwrap();
+        let accept_queue = accept_queue(&dir, Duration::from_secs(3600));
+        // Act
+        accept_queue.restore("abc").unwrap();
+        let mut published = queued[1].clone();
+        published.outcome = Outcome::Published { message_id: None };
+        accept_queue.finish("abc", 1, published.clone());
+        accept_queue.finish("abc", 0, published.clone());
+        // Assert
+        let status = accept_queue.status("abc").unwrap().unwrap();
+        assert!(status.done);
+        assert_eq!(status.events.len(), 2);
+        assert!(accept_queue.spool.accepted("abc").unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
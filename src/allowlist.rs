@@ -0,0 +1,267 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::ErrorForbidden,
+    http::header::{HeaderMap, HeaderName, FORWARDED},
+    middleware::Next,
+    web, Error,
+};
+use ipnet::IpNet;
+use log::warn;
+
+use crate::metrics::METRICS;
+
+/// The source addresses that are allowed to call `/events`.
+///
+/// Requests coming through a trusted proxy, e.g. the OpenShift router, are
+/// judged on the client address in the forwarding header of the proxy, e.g.
+/// `X-Forwarded-For` or `Forwarded`, instead of the address of the proxy.
+pub struct Allowlist {
+    // Everything is allowed when empty.
+    allowed: Vec<IpNet>,
+    trusted_proxies: Vec<IpNet>,
+    trusted_proxy_header: HeaderName,
+    denied: AtomicU64,
+}
+
+/// Parse comma separated CIDR ranges, a single address is a range as well.
+fn parse_ranges(ranges: Option<&str>) -> Result<Vec<IpNet>, String> {
+    ranges
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            range
+                .parse::<IpNet>()
+                .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("Invalid IP range '{}'.", range))
+        })
+        .collect()
+}
+
+impl Allowlist {
+    pub fn new(
+        allowed: Option<&str>,
+        trusted_proxies: Option<&str>,
+        trusted_proxy_header: &str,
+    ) -> Result<Self, String> {
+        Ok(Allowlist {
+            allowed: parse_ranges(allowed)?,
+            trusted_proxies: parse_ranges(trusted_proxies)?,
+            trusted_proxy_header: HeaderName::try_from(trusted_proxy_header)
+                .map_err(|_| format!("Invalid header name '{}'.", trusted_proxy_header))?,
+            denied: AtomicU64::new(0),
+        })
+    }
+
+    fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(ip))
+    }
+
+    /// The address of the client that made the request.
+    ///
+    /// The forwarding header is only honoured when the peer is a trusted
+    /// proxy. It is read from right to left, skipping the trusted proxies,
+    /// so a client can not spoof its address by adding entries itself. A
+    /// node that is not an address, e.g. `unknown`, ends the trusted chain,
+    /// the client is unknown then.
+    pub fn client_ip(&self, peer_addr: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer_ip = peer_addr?.ip();
+        if !self.is_trusted_proxy(&peer_ip) {
+            return Some(peer_ip);
+        }
+        let forwarded_for = forwarded_for(headers, &self.trusted_proxy_header);
+        for ip in forwarded_for.iter().rev() {
+            match ip {
+                Some(ip) if self.is_trusted_proxy(ip) => continue,
+                ip => return *ip,
+            }
+        }
+        // Only proxies in the chain.
+        forwarded_for.first().copied().unwrap_or(Some(peer_ip))
+    }
+
+    pub fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        if self.allowed.is_empty() {
+            return true;
+        }
+        let allowed = ip.is_some_and(|ip| self.allowed.iter().any(|range| range.contains(&ip)));
+        if !allowed {
+            self.denied.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// The number of requests that have been denied.
+    pub fn denied(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+}
+
+/// The nodes in the forwarding header, in the order of the proxy chain. The
+/// `Forwarded` header is parsed as in RFC 7239, any other header as a comma
+/// separated list like `X-Forwarded-For`. A node is `None` when it is not an
+/// address, e.g. `unknown` or an obfuscated identifier.
+fn forwarded_for(headers: &HeaderMap, name: &HeaderName) -> Vec<Option<IpAddr>> {
+    let elements = headers
+        .get_all(name)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    if name == FORWARDED {
+        elements
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value.trim().trim_matches('"')))
+                })
+            })
+            .collect()
+    } else {
+        elements.map(|value| parse_node(value.trim())).collect()
+    }
+}
+
+/// Parse `192.0.2.1`, `192.0.2.1:1234`, `2001:db8::1` or `[2001:db8::1]:1234`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.split_once(']'))
+                .and_then(|(ip, _)| ip.parse().ok())
+        })
+}
+
+/// Middleware rejecting the requests from addresses that are not allowed.
+pub async fn check_allowlist(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let allowlist = req
+        .app_data::<web::Data<Allowlist>>()
+        .expect("The allowlist is registered")
+        .clone();
+    let client_ip = allowlist.client_ip(req.peer_addr(), req.headers());
+    if !allowlist.is_allowed(client_ip) {
+        warn!(
+            "Denied request from {:?} (peer {:?}), {} denied so far.",
+            client_ip,
+            req.peer_addr(),
+            allowlist.denied()
+        );
+        METRICS.requests_denied.inc();
+        return Err(ErrorForbidden("Address not allowed"));
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderValue, X_FORWARDED_FOR};
+
+    fn headers(name: actix_web::http::header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_allowed() {
+        // Arrange
+        let allowlist =
+            Allowlist::new(Some("192.0.2.0/24, 2001:db8::1"), None, "X-Forwarded-For").unwrap();
+        // Act & Assert
+        assert!(allowlist.is_allowed(Some("192.0.2.10".parse().unwrap())));
+        assert!(allowlist.is_allowed(Some("2001:db8::1".parse().unwrap())));
+        assert!(!allowlist.is_allowed(Some("198.51.100.1".parse().unwrap())));
+        assert!(!allowlist.is_allowed(None));
+        assert_eq!(allowlist.denied(), 2);
+    }
+
+    #[test]
+    fn test_empty_allows_everything() {
+        // Arrange
+        let allowlist = Allowlist::new(None, None, "X-Forwarded-For").unwrap();
+        // Act & Assert
+        assert!(allowlist.is_allowed(Some("198.51.100.1".parse().unwrap())));
+    }
+
+    #[test]
+    fn test_invalid_range() {
+        assert!(Allowlist::new(Some("192.0.2.0/33"), None, "X-Forwarded-For").is_err());
+    }
+
+    #[test]
+    fn test_client_ip_untrusted_peer() {
+        // Arrange
+        let allowlist = Allowlist::new(None, Some("10.0.0.0/8"), "X-Forwarded-For").unwrap();
+        let headers = headers(X_FORWARDED_FOR, "192.0.2.10");
+        // Act
+        let client_ip = allowlist.client_ip(Some("198.51.100.1:1234".parse().unwrap()), &headers);
+        // Assert
+        assert_eq!(client_ip, Some("198.51.100.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_ip_x_forwarded_for() {
+        // Arrange
+        let allowlist = Allowlist::new(None, Some("10.0.0.0/8"), "X-Forwarded-For").unwrap();
+        // The client spoofed the first entry.
+        let headers = headers(X_FORWARDED_FOR, "203.0.113.1, 192.0.2.10, 10.1.1.1");
+        // Act
+        let client_ip = allowlist.client_ip(Some("10.0.0.1:1234".parse().unwrap()), &headers);
+        // Assert
+        assert_eq!(client_ip, Some("192.0.2.10".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_ip_forwarded() {
+        // Arrange
+        let allowlist = Allowlist::new(None, Some("10.0.0.0/8"), "Forwarded").unwrap();
+        let headers = headers(
+            FORWARDED,
+            r#"for="[2001:db8::1]:4711";proto=https, for=10.1.1.1"#,
+        );
+        // Act
+        let client_ip = allowlist.client_ip(Some("10.0.0.1:1234".parse().unwrap()), &headers);
+        // Assert
+        assert_eq!(client_ip, Some("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_ip_other_header_ignored() {
+        // Arrange
+        let allowlist = Allowlist::new(None, Some("10.0.0.0/8"), "Forwarded").unwrap();
+        let headers = headers(X_FORWARDED_FOR, "192.0.2.10");
+        // Act
+        let client_ip = allowlist.client_ip(Some("10.0.0.1:1234".parse().unwrap()), &headers);
+        // Assert
+        assert_eq!(client_ip, Some("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_ip_unknown_node() {
+        // Arrange
+        let allowlist =
+            Allowlist::new(Some("192.0.2.0/24"), Some("10.0.0.0/8"), "X-Forwarded-For").unwrap();
+        // The node before the proxy is unknown, the allowed address is spoofed.
+        let headers = headers(X_FORWARDED_FOR, "192.0.2.10, unknown, 10.1.1.1");
+        // Act
+        let client_ip = allowlist.client_ip(Some("10.0.0.1:1234".parse().unwrap()), &headers);
+        // Assert
+        assert_eq!(client_ip, None);
+        assert!(!allowlist.is_allowed(client_ip));
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(Allowlist::new(None, None, "X Forwarded").is_err());
+    }
+}
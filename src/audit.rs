@@ -0,0 +1,351 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use log::{error, info};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::event_result::EventResult;
+
+/// How often the current file is rolled when it got too old and the
+/// expired files are removed, also when no requests come in.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Everything about a single `/events` request, so the original input can be
+/// looked up when a consumer disputes an event.
+#[derive(Serialize, Debug)]
+pub struct AuditRecord<'a> {
+    pub received_at: DateTime<Utc>,
+    pub remote_addr: Option<String>,
+    pub request_id: &'a str,
+    pub correlation_id: &'a str,
+    pub status: u16,
+    pub body: &'a str,
+    pub results: &'a [EventResult],
+}
+
+struct CurrentFile {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    opened_at: SystemTime,
+    size: u64,
+}
+
+/// An archive of JSON lines files with one [`AuditRecord`] per line.
+///
+/// A file is rolled once it reaches `max_file_size` bytes or gets older than
+/// `max_file_age`. Rolled files are gzipped in the background and removed
+/// once they were last written longer than `retention` ago.
+pub struct AuditArchive {
+    dir: PathBuf,
+    max_file_size: u64,
+    max_file_age: Duration,
+    retention: Duration,
+    current: Mutex<Option<CurrentFile>>,
+}
+
+impl AuditArchive {
+    pub fn new(
+        dir: &str,
+        max_file_size: u64,
+        max_file_age: Duration,
+        retention: Duration,
+    ) -> io::Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        let audit_archive = AuditArchive {
+            dir,
+            max_file_size,
+            max_file_age,
+            retention,
+            current: Mutex::new(None),
+        };
+        // Files left behind by a previous run are expired or rolled as well.
+        audit_archive.remove_expired(None)?;
+        for entry in fs::read_dir(&audit_archive.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "jsonl")
+            {
+                compress_in_background(path);
+            }
+        }
+        Ok(audit_archive)
+    }
+
+    /// Roll the current file when it got too old and remove the expired
+    /// files every [`MAINTENANCE_INTERVAL`].
+    pub fn watch(self: Arc<Self>) {
+        thread::spawn(move || loop {
+            thread::sleep(MAINTENANCE_INTERVAL);
+            if let Err(e) = self.maintain() {
+                error!("Could not maintain the audit archive: {}", e);
+            }
+        });
+    }
+
+    fn maintain(&self) -> io::Result<()> {
+        let mut current = self.current.lock().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|current_file| self.is_full(current_file))
+        {
+            return self.roll(current.take().unwrap());
+        }
+        self.remove_expired(
+            current
+                .as_ref()
+                .map(|current_file| current_file.path.as_path()),
+        )
+    }
+
+    fn is_full(&self, current_file: &CurrentFile) -> bool {
+        let age = current_file.opened_at.elapsed().unwrap_or_default();
+        current_file.size >= self.max_file_size || age >= self.max_file_age
+    }
+
+    pub fn write(&self, record: &AuditRecord) {
+        if let Err(e) = self.try_write(record) {
+            error!(
+                "Could not write request '{}' to the audit archive: {}",
+                record.request_id, e
+            );
+        }
+    }
+
+    fn try_write(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut current = self.current.lock().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|current_file| self.is_full(current_file))
+        {
+            self.roll(current.take().unwrap())?;
+        }
+        if current.is_none() {
+            // The random part keeps files opened in the same millisecond apart.
+            let path = self.dir.join(format!(
+                "audit-{}-{}.jsonl",
+                Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+                &Uuid::new_v4().to_simple().to_string()[..8]
+            ));
+            let file = OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(&path)?;
+            *current = Some(CurrentFile {
+                writer: BufWriter::new(file),
+                path,
+                opened_at: SystemTime::now(),
+                size: 0,
+            });
+        }
+        let current_file = current.as_mut().unwrap();
+        current_file.writer.write_all(&line)?;
+        current_file.writer.flush()?;
+        current_file.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn roll(&self, mut current_file: CurrentFile) -> io::Result<()> {
+        current_file.writer.flush()?;
+        compress_in_background(current_file.path);
+        self.remove_expired(None)
+    }
+
+    /// Remove the files older than the retention, except the current one.
+    /// Files that were not compressed yet, e.g. when the bridge was killed,
+    /// are removed as well.
+    fn remove_expired(&self, current: Option<&Path>) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = path.to_string_lossy();
+            if !(name.ends_with(".jsonl.gz") || name.ends_with(".jsonl"))
+                || current == Some(path.as_path())
+            {
+                continue;
+            }
+            let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+            if age > self.retention {
+                fs::remove_file(&path)?;
+                info!("Removed expired audit file '{}'.", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Close and compress the current file, before the process exits.
+    pub fn close(&self) {
+        if let Some(mut current_file) = self.current.lock().unwrap().take() {
+            let closed = current_file
+                .writer
+                .flush()
+                .and_then(|()| compress(&current_file.path));
+            if let Err(e) = closed {
+                error!(
+                    "Could not compress audit file '{}': {}",
+                    current_file.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn compress_in_background(path: PathBuf) {
+    thread::spawn(move || {
+        if let Err(e) = compress(&path) {
+            error!("Could not compress audit file '{}': {}", path.display(), e);
+        }
+    });
+}
+
+/// Gzip `{path}` to `{path}.gz` and remove the original. The compressed file
+/// keeps the modification time, the retention counts from it.
+fn compress(path: &Path) -> io::Result<()> {
+    let mut compressed_path = path.as_os_str().to_owned();
+    compressed_path.push(".gz");
+    let file = File::open(path)?;
+    let modified = file.metadata()?.modified()?;
+    let mut reader = BufReader::new(file);
+    let mut encoder = GzEncoder::new(File::create(&compressed_path)?, Compression::default());
+    io::copy(&mut reader, &mut encoder)?;
+    let compressed = encoder.finish()?;
+    compressed.set_modified(modified)?;
+    compressed.sync_all()?;
+    fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_result::Outcome;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_compress() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        fs::write(&path, "{}\n").unwrap();
+        // Act
+        compress(&path).unwrap();
+        // Assert
+        assert!(!path.exists());
+        let mut contents = String::new();
+        GzDecoder::new(File::open(dir.join("audit.jsonl.gz")).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "{}\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_rolls_on_size() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let archive = AuditArchive::new(
+            dir.to_str().unwrap(),
+            1,
+            Duration::from_secs(3600),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        let results = vec![EventResult {
+            identifier: String::from("111"),
+            event_type: String::from("FLOW.ARCHIVED"),
+            topic: String::from("be.mediahaven.flow.archived"),
+            outcome: Outcome::Published {
+                message_id: Some(String::from("42:7:-1")),
+            },
+        }];
+        let record = AuditRecord {
+            received_at: Utc::now(),
+            remote_addr: Some(String::from("127.0.0.1:1234")),
+            request_id: "abc",
+            correlation_id: "abc",
+            status: 200,
+            body: "<events/>",
+            results: &results,
+        };
+        // Act
+        archive.write(&record);
+        let first_path = archive
+            .current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .path
+            .clone();
+        archive.write(&record);
+        // Assert
+        let current = archive.current.lock().unwrap();
+        let current_file = current.as_ref().unwrap();
+        assert_ne!(current_file.path, first_path);
+        let contents = fs::read_to_string(&current_file.path).unwrap();
+        assert!(contents.contains(r#""request_id":"abc""#));
+        assert!(contents.contains(r#""outcome":"published""#));
+        drop(current);
+        // Wait for the first file to be compressed.
+        for _ in 0..100 {
+            if !first_path.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_close_and_expire_stray_files() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let stray_path = dir.join("audit-20190330T052840.000.jsonl");
+        fs::write(&stray_path, "{}\n").unwrap();
+        File::options()
+            .write(true)
+            .open(&stray_path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+        // Act
+        let archive = AuditArchive::new(
+            dir.to_str().unwrap(),
+            1000,
+            Duration::from_secs(3600),
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        archive.write(&AuditRecord {
+            received_at: Utc::now(),
+            remote_addr: None,
+            request_id: "abc",
+            correlation_id: "abc",
+            status: 200,
+            body: "<events/>",
+            results: &[],
+        });
+        archive.close();
+        // Assert
+        let names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].ends_with(".jsonl.gz"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,387 @@
+use std::fs;
+use std::io;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::{ErrorPayloadTooLarge, ErrorUnauthorized},
+    http::{
+        header::{HeaderMap, AUTHORIZATION},
+        StatusCode,
+    },
+    middleware::Next,
+    web, Error,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::warn;
+use sha2::Sha256;
+
+use crate::metrics::METRICS;
+use mh_events2pulsar::{AuthMode, Config};
+
+/// Checks the credentials of a request against the configured secrets.
+///
+/// Several secrets can be valid at the same time, so they can be rotated
+/// without downtime.
+pub struct Authenticator {
+    mode: AuthMode,
+    secrets: Vec<String>,
+    hmac_header: String,
+    hmac_timestamp_header: String,
+    hmac_max_skew: u64,
+    max_body_size: usize,
+}
+
+impl Authenticator {
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let mut secrets: Vec<String> = Vec::new();
+        if let Some(auth_secrets) = &config.auth_secrets {
+            secrets.extend(
+                auth_secrets
+                    .split(',')
+                    .map(|secret| secret.trim().to_string()),
+            );
+        }
+        // One secret per line.
+        if let Some(path) = &config.auth_secrets_file {
+            secrets.extend(
+                fs::read_to_string(path)?
+                    .lines()
+                    .map(|secret| secret.trim().to_string()),
+            );
+        }
+        secrets.retain(|secret| !secret.is_empty());
+        if config.auth_mode != AuthMode::None && secrets.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "No secrets configured for {:?} authentication.",
+                    config.auth_mode
+                ),
+            ));
+        }
+        Ok(Authenticator {
+            mode: config.auth_mode,
+            secrets,
+            hmac_header: config.auth_hmac_header.clone(),
+            hmac_timestamp_header: config.auth_hmac_timestamp_header.clone(),
+            hmac_max_skew: config.auth_hmac_max_skew,
+            max_body_size: config.max_body_size,
+        })
+    }
+
+    pub fn mode(&self) -> AuthMode {
+        self.mode
+    }
+
+    /// Check the credentials in the headers, and the signature of the body
+    /// for HMAC authentication.
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), &'static str> {
+        match self.mode {
+            AuthMode::None => Ok(()),
+            AuthMode::Basic => {
+                let credentials = header(headers, AUTHORIZATION.as_str())
+                    .and_then(|value| value.strip_prefix("Basic "))
+                    .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+                    .ok_or("Missing basic credentials")?;
+                self.matches_secret(&credentials)
+                    .then_some(())
+                    .ok_or("Invalid basic credentials")
+            }
+            AuthMode::Bearer => {
+                let token = header(headers, AUTHORIZATION.as_str())
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .ok_or("Missing bearer token")?;
+                self.matches_secret(token.trim().as_bytes())
+                    .then_some(())
+                    .ok_or("Invalid bearer token")
+            }
+            AuthMode::Hmac => {
+                let signature = header(headers, &self.hmac_header)
+                    .map(|value| value.trim_start_matches("sha256="))
+                    .and_then(|value| hex::decode(value.trim()).ok())
+                    .ok_or("Missing signature")?;
+                // The timestamp is signed along with the body, so an old
+                // request can not be replayed.
+                let mut signed = Vec::new();
+                if self.hmac_max_skew > 0 {
+                    let timestamp = header(headers, &self.hmac_timestamp_header)
+                        .and_then(|value| value.trim().parse::<i64>().ok())
+                        .ok_or("Missing timestamp")?;
+                    if Utc::now().timestamp().abs_diff(timestamp) > self.hmac_max_skew {
+                        return Err("Timestamp outside of the allowed window");
+                    }
+                    signed.extend_from_slice(format!("{}.", timestamp).as_bytes());
+                }
+                signed.extend_from_slice(body);
+                let valid = self.secrets.iter().any(|secret| {
+                    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                        .expect("HMAC can take a key of any size");
+                    mac.update(&signed);
+                    mac.verify_slice(&signature).is_ok()
+                });
+                valid.then_some(()).ok_or("Invalid signature")
+            }
+        }
+    }
+
+    fn matches_secret(&self, credentials: &[u8]) -> bool {
+        // Check every secret, so the time taken does not tell which one matched.
+        self.secrets.iter().fold(false, |found, secret| {
+            constant_time_eq(secret.as_bytes(), credentials) | found
+        })
+    }
+}
+
+/// Checks the bearer token of the requests to the `/admin` endpoints, which
+/// is separate from the credentials of MediaHaven.
+pub struct AdminAuthenticator(Authenticator);
+
+impl AdminAuthenticator {
+    /// `None` when no admin token is configured.
+    pub fn new(config: &Config) -> Option<Self> {
+        let tokens: Vec<String> = config
+            .admin_token
+            .as_deref()?
+            .split(',')
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .collect();
+        if tokens.is_empty() {
+            return None;
+        }
+        Some(AdminAuthenticator(Authenticator {
+            mode: AuthMode::Bearer,
+            secrets: tokens,
+            hmac_header: config.auth_hmac_header.clone(),
+            hmac_timestamp_header: config.auth_hmac_timestamp_header.clone(),
+            hmac_max_skew: 0,
+            max_body_size: config.max_body_size,
+        }))
+    }
+
+    pub fn verify(&self, headers: &HeaderMap) -> Result<(), &'static str> {
+        self.0.verify(headers, &[])
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Middleware rejecting the requests that do not pass the [`Authenticator`].
+pub async fn authenticate(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let authenticator = req
+        .app_data::<web::Data<Authenticator>>()
+        .expect("The authenticator is registered")
+        .clone();
+    let verified = if authenticator.mode() == AuthMode::Hmac {
+        // Read the body to check its signature and put it back for the
+        // handler. The signature is over the body as sent, so a compressed
+        // body is not decompressed here.
+        let payload = req.extract::<web::Payload>().await?;
+        let body = match payload.to_bytes_limited(authenticator.max_body_size).await {
+            Ok(body) => body?,
+            Err(_) => {
+                return Err(ErrorPayloadTooLarge(format!(
+                    "The request body exceeds the limit of {} bytes.",
+                    authenticator.max_body_size
+                )))
+            }
+        };
+        let verified = authenticator.verify(req.headers(), &body);
+        let (_, mut payload) = actix_http::h1::Payload::create(true);
+        payload.unread_data(body);
+        req.set_payload(payload.into());
+        verified
+    } else {
+        authenticator.verify(req.headers(), &[])
+    };
+    if let Err(reason) = verified {
+        warn!(
+            "Rejected unauthenticated request from {:?}: {}",
+            req.peer_addr(),
+            reason
+        );
+        METRICS.reject(StatusCode::UNAUTHORIZED, "unauthenticated");
+        return Err(ErrorUnauthorized(reason));
+    }
+    next.call(req).await
+}
+
+/// Middleware rejecting the requests that do not pass the
+/// [`AdminAuthenticator`].
+pub async fn authenticate_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let admin_authenticator = req
+        .app_data::<web::Data<AdminAuthenticator>>()
+        .expect("The admin authenticator is registered")
+        .clone();
+    if let Err(reason) = admin_authenticator.verify(req.headers()) {
+        warn!(
+            "Rejected unauthenticated admin request from {:?}: {}",
+            req.peer_addr(),
+            reason
+        );
+        METRICS.reject(StatusCode::UNAUTHORIZED, "unauthenticated");
+        return Err(ErrorUnauthorized(reason));
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn authenticator(mode: AuthMode, secrets: &[&str], hmac_max_skew: u64) -> Authenticator {
+        Authenticator {
+            mode,
+            secrets: secrets.iter().map(|secret| secret.to_string()).collect(),
+            hmac_header: String::from("X-Signature"),
+            hmac_timestamp_header: String::from("X-Timestamp"),
+            hmac_max_skew,
+            max_body_size: 1000,
+        }
+    }
+
+    fn headers(headers: &[(&'static str, String)]) -> HeaderMap {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        header_map
+    }
+
+    fn sign(secret: &str, signed: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(signed);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn test_bearer() {
+        // Arrange
+        let authenticator = authenticator(AuthMode::Bearer, &["old", "new"], 0);
+        // Act
+        let old = authenticator.verify(&headers(&[("authorization", "Bearer old".into())]), &[]);
+        let new = authenticator.verify(&headers(&[("authorization", "Bearer new".into())]), &[]);
+        let wrong =
+            authenticator.verify(&headers(&[("authorization", "Bearer wrong".into())]), &[]);
+        let missing = authenticator.verify(&headers(&[]), &[]);
+        // Assert
+        assert!(old.is_ok());
+        assert!(new.is_ok());
+        assert!(wrong.is_err());
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_admin() {
+        // Arrange
+        let mut config: Config = envy::from_iter(vec![(
+            String::from("AUTH_SECRETS"),
+            String::from("webhook"),
+        )])
+        .unwrap();
+        config.auth_mode = AuthMode::Bearer;
+        config.admin_token = Some(String::from("operator"));
+        let admin_authenticator = AdminAuthenticator::new(&config).unwrap();
+        // Act
+        let operator =
+            admin_authenticator.verify(&headers(&[("authorization", "Bearer operator".into())]));
+        let webhook =
+            admin_authenticator.verify(&headers(&[("authorization", "Bearer webhook".into())]));
+        // Assert
+        assert!(operator.is_ok());
+        assert!(webhook.is_err());
+        config.admin_token = None;
+        assert!(AdminAuthenticator::new(&config).is_none());
+    }
+
+    #[test]
+    fn test_basic() {
+        // Arrange
+        let authenticator = authenticator(AuthMode::Basic, &["mediahaven:secret"], 0);
+        let valid = format!("Basic {}", STANDARD.encode("mediahaven:secret"));
+        let invalid = format!("Basic {}", STANDARD.encode("mediahaven:wrong"));
+        // Act
+        let valid = authenticator.verify(&headers(&[("authorization", valid)]), &[]);
+        let invalid = authenticator.verify(&headers(&[("authorization", invalid)]), &[]);
+        // Assert
+        assert!(valid.is_ok());
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_hmac() {
+        // Arrange
+        let authenticator = authenticator(AuthMode::Hmac, &["secret"], 300);
+        let body = b"<events/>";
+        let now = Utc::now().timestamp();
+        let signed = [format!("{}.", now).as_bytes(), body].concat();
+        let signature = format!("sha256={}", sign("secret", &signed));
+        // Act
+        let valid = authenticator.verify(
+            &headers(&[
+                ("x-signature", signature.clone()),
+                ("x-timestamp", now.to_string()),
+            ]),
+            body,
+        );
+        let tampered = authenticator.verify(
+            &headers(&[
+                ("x-signature", signature.clone()),
+                ("x-timestamp", now.to_string()),
+            ]),
+            b"<events></events>",
+        );
+        let replayed = authenticator.verify(
+            &headers(&[
+                ("x-signature", signature.clone()),
+                ("x-timestamp", (now - 600).to_string()),
+            ]),
+            body,
+        );
+        let overflowing = authenticator.verify(
+            &headers(&[
+                ("x-signature", signature),
+                ("x-timestamp", i64::MIN.to_string()),
+            ]),
+            body,
+        );
+        // Assert
+        assert!(valid.is_ok());
+        assert!(tampered.is_err());
+        assert!(replayed.is_err());
+        assert_eq!(
+            overflowing.unwrap_err(),
+            "Timestamp outside of the allowed window"
+        );
+    }
+
+    #[test]
+    fn test_hmac_without_timestamp() {
+        // Arrange
+        let authenticator = authenticator(AuthMode::Hmac, &["secret"], 0);
+        let body = b"<events/>";
+        // Act
+        let valid = authenticator.verify(&headers(&[("x-signature", sign("secret", body))]), body);
+        // Assert
+        assert!(valid.is_ok());
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+
+use actix_web::{
+    http::{
+        header::{AsHeaderName, HeaderMap, CONTENT_ENCODING, CONTENT_TYPE},
+        StatusCode,
+    },
+    HttpResponse, ResponseError,
+};
+use encoding_rs::{Encoding, UTF_8};
+
+const XML_MEDIA_TYPES: [&str; 2] = ["application/xml", "text/xml"];
+const JSON_MEDIA_TYPE: &str = "application/json";
+
+/// The content codings that are decompressed before the body is read.
+const CONTENT_CODINGS: [&str; 5] = ["identity", "gzip", "x-gzip", "deflate", "zstd"];
+
+/// The formats of the premis events accepted on `/events`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyFormat {
+    /// Premis XML, as sent by MediaHaven.
+    Xml,
+    /// The same fields as JSON, see [`mh_events2pulsar::Event::from_json`].
+    Json,
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    UnsupportedMediaType(String),
+    UnsupportedEncoding(String),
+    InvalidCharset(String),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::UnsupportedMediaType(media_type) => write!(
+                f,
+                "Unsupported content type '{}', expected one of: {}, {}.",
+                media_type,
+                XML_MEDIA_TYPES.join(", "),
+                JSON_MEDIA_TYPE
+            ),
+            BodyError::UnsupportedEncoding(coding) => {
+                write!(f, "Unsupported content encoding '{}'.", coding)
+            }
+            BodyError::InvalidCharset(charset) => {
+                write!(f, "The body is not valid '{}'.", charset)
+            }
+        }
+    }
+}
+
+impl ResponseError for BodyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BodyError::UnsupportedMediaType(_) | BodyError::UnsupportedEncoding(_) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            BodyError::InvalidCharset(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+fn header(headers: &HeaderMap, name: impl AsHeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// The media type and charset parameter of the `Content-Type` header.
+fn content_type(headers: &HeaderMap) -> Option<(String, Option<String>)> {
+    let content_type = header(headers, CONTENT_TYPE)?;
+    let mut parts = content_type.split(';');
+    let media_type = parts.next().unwrap_or_default().trim().to_lowercase();
+    let charset = parts.find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"').to_string())
+    });
+    Some((media_type, charset))
+}
+
+/// The format of the body, checking that it is in a content coding that is
+/// decompressed.
+///
+/// A request without `Content-Type` is taken to be XML.
+pub fn check_headers(headers: &HeaderMap) -> Result<BodyFormat, BodyError> {
+    let format = match content_type(headers) {
+        None => BodyFormat::Xml,
+        Some((media_type, _)) if XML_MEDIA_TYPES.contains(&media_type.as_str()) => BodyFormat::Xml,
+        Some((media_type, _)) if media_type == JSON_MEDIA_TYPE => BodyFormat::Json,
+        Some((media_type, _)) => return Err(BodyError::UnsupportedMediaType(media_type)),
+    };
+    if let Some(content_encoding) = header(headers, CONTENT_ENCODING) {
+        for coding in content_encoding
+            .split(',')
+            .map(|coding| coding.trim().to_lowercase())
+        {
+            if !CONTENT_CODINGS.contains(&coding.as_str()) {
+                return Err(BodyError::UnsupportedEncoding(coding));
+            }
+        }
+    }
+    Ok(format)
+}
+
+/// The encoding declared in the XML declaration, e.g. `ISO-8859-1` in
+/// `<?xml version="1.0" encoding="ISO-8859-1"?>`.
+fn xml_declaration_encoding(body: &[u8]) -> Option<String> {
+    // The declaration is ASCII in every encoding that can declare itself.
+    let start = body.strip_prefix(b"<?xml")?;
+    let end = start.windows(2).position(|window| window == b"?>")?;
+    let declaration = std::str::from_utf8(&start[..end]).ok()?;
+    let (_, rest) = declaration.split_once("encoding")?;
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    let quote = rest
+        .chars()
+        .next()
+        .filter(|quote| *quote == '"' || *quote == '\'')?;
+    let (encoding, _) = rest[1..].split_once(quote)?;
+    Some(encoding.to_string())
+}
+
+/// Remove the XML declaration, as it no longer applies after transcoding.
+fn strip_xml_declaration(body: &str) -> &str {
+    match body
+        .strip_prefix("<?xml")
+        .and_then(|rest| rest.split_once("?>"))
+    {
+        Some((_, rest)) => rest,
+        None => body,
+    }
+}
+
+/// Decode the body to a string.
+///
+/// The charset of the `Content-Type` header takes precedence over the
+/// encoding in the XML declaration, a byte order mark over both of them.
+/// Without any of those the body has to be UTF-8.
+pub fn decode(headers: &HeaderMap, body: &[u8]) -> Result<String, BodyError> {
+    let label = content_type(headers)
+        .and_then(|(_, charset)| charset)
+        .or_else(|| xml_declaration_encoding(body));
+    let encoding = match &label {
+        Some(label) => Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| BodyError::InvalidCharset(label.clone()))?,
+        None => UTF_8,
+    };
+    let (decoded, used_encoding, had_errors) = encoding.decode(body);
+    if had_errors {
+        return Err(BodyError::InvalidCharset(used_encoding.name().to_string()));
+    }
+    if used_encoding == UTF_8 {
+        Ok(decoded.into_owned())
+    } else {
+        Ok(strip_xml_declaration(&decoded).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(headers: &[(HeaderName, &str)]) -> HeaderMap {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        header_map
+    }
+
+    #[test]
+    fn test_check_headers() {
+        assert_eq!(check_headers(&headers(&[])).unwrap(), BodyFormat::Xml);
+        assert_eq!(
+            check_headers(&headers(&[(CONTENT_TYPE, "application/xml")])).unwrap(),
+            BodyFormat::Xml
+        );
+        assert_eq!(
+            check_headers(&headers(&[(CONTENT_TYPE, "text/xml; charset=utf-8")])).unwrap(),
+            BodyFormat::Xml
+        );
+        assert_eq!(
+            check_headers(&headers(&[(CONTENT_TYPE, "application/json")])).unwrap(),
+            BodyFormat::Json
+        );
+        assert!(matches!(
+            check_headers(&headers(&[(CONTENT_TYPE, "text/plain")])),
+            Err(BodyError::UnsupportedMediaType(_))
+        ));
+        assert!(check_headers(&headers(&[(CONTENT_ENCODING, "gzip")])).is_ok());
+        assert!(matches!(
+            check_headers(&headers(&[(CONTENT_ENCODING, "compress")])),
+            Err(BodyError::UnsupportedEncoding(_))
+        ));
+        // actix-web is built without brotli support.
+        assert!(matches!(
+            check_headers(&headers(&[(CONTENT_ENCODING, "br")])),
+            Err(BodyError::UnsupportedEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_utf8() {
+        // Act
+        let body = decode(&headers(&[]), "<events>é</events>".as_bytes()).unwrap();
+        // Assert
+        assert_eq!(body, "<events>é</events>");
+    }
+
+    #[test]
+    fn test_decode_invalid_utf8() {
+        // Act
+        let body = decode(&headers(&[]), b"<events>\xe9</events>");
+        // Assert
+        assert!(matches!(body, Err(BodyError::InvalidCharset(_))));
+    }
+
+    #[test]
+    fn test_decode_charset_header() {
+        // Act
+        let body = decode(
+            &headers(&[(CONTENT_TYPE, "application/xml; charset=ISO-8859-1")]),
+            b"<events>\xe9</events>",
+        )
+        .unwrap();
+        // Assert
+        assert_eq!(body, "<events>é</events>");
+    }
+
+    #[test]
+    fn test_decode_xml_declaration() {
+        // Act
+        let body = decode(
+            &headers(&[(CONTENT_TYPE, "application/xml")]),
+            b"<?xml version=\"1.0\" encoding='ISO-8859-1'?><events>\xe9</events>",
+        )
+        .unwrap();
+        // Assert
+        assert_eq!(body, "<events>é</events>");
+    }
+}
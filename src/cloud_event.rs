@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::SecondsFormat;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const SPEC_VERSION: &str = "1.0";
+
+/// A CloudEvents 1.0 envelope, in the JSON event format.
+///
+/// The extension attributes sit next to the context attributes, so their
+/// names have to be lowercase letters and digits.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    /// A URI-reference of where the event happened.
+    pub source: String,
+    /// Reverse-DNS, e.g. `be.mediahaven.flow.archived`.
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub time: DateTime<Utc>,
+    pub subject: String,
+    pub datacontenttype: String,
+    pub data: serde_json::Value,
+    #[serde(flatten)]
+    pub extensions: BTreeMap<String, String>,
+}
+
+impl CloudEvent {
+    /// The attributes as `ce_` prefixed message properties, for the binary
+    /// content mode. The data is the payload.
+    pub fn binary_properties(&self) -> HashMap<String, String> {
+        let mut properties = HashMap::from([
+            (String::from("ce_specversion"), self.specversion.clone()),
+            (String::from("ce_id"), self.id.clone()),
+            (String::from("ce_source"), self.source.clone()),
+            (String::from("ce_type"), self.event_type.clone()),
+            (
+                String::from("ce_time"),
+                self.time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            ),
+            (String::from("ce_subject"), self.subject.clone()),
+            (
+                String::from("ce_datacontenttype"),
+                self.datacontenttype.clone(),
+            ),
+        ]);
+        for (name, value) in &self.extensions {
+            properties.insert(format!("ce_{}", name), value.clone());
+        }
+        properties
+    }
+}
+
+/// Whether `value` is a URI-reference as in RFC 3986: an absolute URI or a
+/// relative reference, without characters that have to be escaped.
+pub fn is_uri_reference(value: &str) -> bool {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || "-._~:/?#[]@!$&'()*+,;=%".contains(c);
+    if value.is_empty() || !value.chars().all(allowed) {
+        return false;
+    }
+    match value.split_once(':') {
+        // A scheme starts with a letter, before any `/`, `?` or `#`.
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const REQUIRED: [&str; 4] = ["id", "source", "specversion", "type"];
+    const OPTIONAL: [&str; 5] = ["datacontenttype", "dataschema", "subject", "time", "data"];
+
+    fn is_valid_extension_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    }
+
+    /// The violations of the CloudEvents 1.0 spec and its JSON event format
+    /// in a structured mode message.
+    pub fn conformance_errors(envelope: &Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        let attributes = match envelope.as_object() {
+            Some(attributes) => attributes,
+            None => return vec![String::from("The envelope is not a JSON object.")],
+        };
+        for name in REQUIRED {
+            match attributes.get(name).and_then(Value::as_str) {
+                Some(value) if !value.is_empty() => {}
+                _ => errors.push(format!("Missing required attribute '{}'.", name)),
+            }
+        }
+        if attributes.get("specversion") != Some(&json!(SPEC_VERSION)) {
+            errors.push(String::from("The specversion is not 1.0."));
+        }
+        if let Some(source) = attributes.get("source").and_then(Value::as_str) {
+            if !is_uri_reference(source) {
+                errors.push(format!("The source '{}' is not a URI-reference.", source));
+            }
+        }
+        if let Some(time) = attributes.get("time") {
+            if time
+                .as_str()
+                .is_none_or(|time| DateTime::parse_from_rfc3339(time).is_err())
+            {
+                errors.push(String::from("The time is not an RFC 3339 timestamp."));
+            }
+        }
+        if attributes.contains_key("data") && attributes.contains_key("data_base64") {
+            errors.push(String::from("Both data and data_base64 are set."));
+        }
+        for (name, value) in attributes {
+            if REQUIRED.contains(&name.as_str())
+                || OPTIONAL.contains(&name.as_str())
+                || name == "data_base64"
+            {
+                continue;
+            }
+            if !is_valid_extension_name(name) {
+                errors.push(format!("The attribute name '{}' is invalid.", name));
+            }
+            if name.len() > 20 {
+                errors.push(format!("The attribute name '{}' is too long.", name));
+            }
+            if value.is_null() || value.is_object() || value.is_array() {
+                errors.push(format!("The extension '{}' is not a scalar.", name));
+            }
+        }
+        errors
+    }
+
+    fn cloud_event() -> CloudEvent {
+        CloudEvent {
+            specversion: SPEC_VERSION.to_string(),
+            id: String::from("111"),
+            source: String::from("urn:meemoo:mh-events2pulsar"),
+            event_type: String::from("be.mediahaven.flow.archived"),
+            time: "2019-03-30T05:28:40Z".parse().unwrap(),
+            subject: String::from("a1"),
+            datacontenttype: String::from("application/json"),
+            data: json!({ "premis": "<premis:event/>" }),
+            extensions: BTreeMap::from([(String::from("correlationid"), String::from("abc"))]),
+        }
+    }
+
+    #[test]
+    fn test_conformance() {
+        let envelope = serde_json::to_value(cloud_event()).unwrap();
+        assert_eq!(conformance_errors(&envelope), Vec::<String>::new());
+        assert_eq!(envelope["type"], "be.mediahaven.flow.archived");
+        assert_eq!(envelope["time"], "2019-03-30T05:28:40Z");
+        assert_eq!(envelope["correlationid"], "abc");
+    }
+
+    #[test]
+    fn test_conformance_violations() {
+        // Arrange
+        let mut envelope = serde_json::to_value(cloud_event()).unwrap();
+        envelope["source"] = json!("mh events2pulsar");
+        envelope["content_type"] = json!("application/cloudevents+json");
+        envelope["time"] = json!("yesterday");
+        envelope.as_object_mut().unwrap().remove("id");
+        // Act
+        let errors = conformance_errors(&envelope);
+        // Assert
+        assert_eq!(
+            errors,
+            vec![
+                "Missing required attribute 'id'.",
+                "The source 'mh events2pulsar' is not a URI-reference.",
+                "The time is not an RFC 3339 timestamp.",
+                "The attribute name 'content_type' is invalid.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uri_reference() {
+        assert!(is_uri_reference("urn:meemoo:mh-events2pulsar"));
+        assert!(is_uri_reference("https://mediahaven.example.org/events"));
+        assert!(is_uri_reference("/mh-events2pulsar"));
+        assert!(!is_uri_reference("1http://example.org"));
+        assert!(!is_uri_reference(""));
+    }
+}
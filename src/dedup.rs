@@ -0,0 +1,351 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use log::error;
+use sha2::{Digest, Sha256};
+
+use mh_events2pulsar::Event;
+
+/// Remembers which premis events have been published, so a redelivery by
+/// MediaHaven is acknowledged without publishing the event again.
+///
+/// Events are keyed on the `MEDIAHAVEN_EVENT` identifier, optionally combined
+/// with a hash of the premis XML. At most `capacity` keys are kept, each for
+/// `ttl` seconds. With a journal the keys are also appended to a file, which
+/// is read back on startup so redeliveries are recognised across restarts.
+/// The journal is compacted once it holds more than `capacity` lines that
+/// are not needed anymore.
+pub struct Dedup {
+    capacity: usize,
+    ttl: i64,
+    content_hash: bool,
+    seen: Mutex<Seen>,
+    journal: Option<Mutex<Journal>>,
+    duplicates: AtomicU64,
+}
+
+#[derive(Default)]
+struct Seen {
+    // Key to the unix timestamp it was remembered at.
+    keys: HashMap<String, i64>,
+    // The keys in the order they were remembered, oldest first.
+    order: VecDeque<(String, i64)>,
+    // The keys of the events that are being published.
+    reserved: HashSet<String>,
+}
+
+struct Journal {
+    path: PathBuf,
+    file: File,
+    lines: usize,
+}
+
+impl Journal {
+    /// Rewrite the journal with only the remembered keys.
+    fn create(path: PathBuf, order: &VecDeque<(String, i64)>) -> io::Result<Self> {
+        let mut compacted_path = path.clone().into_os_string();
+        compacted_path.push(".compacted");
+        let mut writer = BufWriter::new(File::create(&compacted_path)?);
+        for (key, seen_at) in order {
+            writeln!(writer, "{}\t{}", key, seen_at)?;
+        }
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&compacted_path, &path)?;
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Journal {
+            path,
+            file,
+            lines: order.len(),
+        })
+    }
+}
+
+/// Whether an event can be published, see [`Dedup::reserve`].
+pub enum Claim<'a> {
+    /// Not published yet, the key is reserved until the reservation is
+    /// remembered or dropped.
+    New(Reservation<'a>),
+    /// Already published.
+    Duplicate,
+    /// Being published by another request.
+    InFlight,
+}
+
+/// The reserved key of an event that is being published. It is released
+/// when dropped, without being remembered when sending failed.
+pub struct Reservation<'a> {
+    dedup: &'a Dedup,
+    key: String,
+}
+
+impl Reservation<'_> {
+    /// Remember that the event has been published.
+    pub fn remember(self) {
+        self.dedup.remember(&self.key);
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.dedup.capacity > 0 {
+            let mut seen = self.dedup.seen.lock().unwrap();
+            seen.reserved.remove(&self.key);
+        }
+    }
+}
+
+impl Seen {
+    fn insert(&mut self, key: String, seen_at: i64) {
+        self.keys.insert(key.clone(), seen_at);
+        self.order.push_back((key, seen_at));
+    }
+
+    /// Drop the keys that are expired or do not fit anymore.
+    fn evict(&mut self, capacity: usize, expired_before: i64) {
+        while let Some((key, seen_at)) = self.order.front() {
+            if self.order.len() <= capacity && *seen_at >= expired_before {
+                break;
+            }
+            // A key remembered twice is only removed together with its latest entry.
+            if self.keys.get(key) == Some(seen_at) {
+                self.keys.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+impl Dedup {
+    /// A `capacity` of 0 disables the deduplication.
+    pub fn new(
+        capacity: usize,
+        ttl: u64,
+        content_hash: bool,
+        journal: Option<&str>,
+    ) -> io::Result<Self> {
+        let ttl = ttl as i64;
+        let mut seen = Seen::default();
+        let journal = match journal {
+            Some(path) if capacity > 0 => {
+                let expired_before = Utc::now().timestamp() - ttl;
+                if let Ok(contents) = fs::read_to_string(path) {
+                    for line in contents.lines() {
+                        if let Some((key, seen_at)) = line.rsplit_once('\t') {
+                            if let Ok(seen_at) = seen_at.parse::<i64>() {
+                                seen.insert(key.to_string(), seen_at);
+                            }
+                        }
+                    }
+                }
+                seen.evict(capacity, expired_before);
+                // Compact the journal to the keys that are still relevant.
+                Some(Mutex::new(Journal::create(
+                    PathBuf::from(path),
+                    &seen.order,
+                )?))
+            }
+            _ => None,
+        };
+        Ok(Dedup {
+            capacity,
+            ttl,
+            content_hash,
+            seen: Mutex::new(seen),
+            journal,
+            duplicates: AtomicU64::new(0),
+        })
+    }
+
+    /// The key identifying a premis event.
+    pub fn key(&self, event: &Event) -> String {
+        if self.content_hash {
+            format!(
+                "{}:{:x}",
+                event.identifier(),
+                Sha256::digest(event.to_xml().as_bytes())
+            )
+        } else {
+            event.identifier().to_string()
+        }
+    }
+
+    /// Reserve the key of an event before publishing it, so a redelivery
+    /// that arrives at the same time is not published as well. Duplicates
+    /// are counted.
+    pub fn reserve(&self, key: &str) -> Claim<'_> {
+        if self.capacity > 0 {
+            let expired_before = Utc::now().timestamp() - self.ttl;
+            let mut seen = self.seen.lock().unwrap();
+            seen.evict(self.capacity, expired_before);
+            if seen.keys.contains_key(key) {
+                self.duplicates.fetch_add(1, Ordering::Relaxed);
+                return Claim::Duplicate;
+            }
+            if !seen.reserved.insert(key.to_string()) {
+                return Claim::InFlight;
+            }
+        }
+        Claim::New(Reservation {
+            dedup: self,
+            key: key.to_string(),
+        })
+    }
+
+    /// Whether the key is remembered, without counting it as a duplicate.
+    pub fn contains(&self, key: &str) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        let expired_before = Utc::now().timestamp() - self.ttl;
+        let mut seen = self.seen.lock().unwrap();
+        seen.evict(self.capacity, expired_before);
+        seen.keys.contains_key(key)
+    }
+
+    /// Remember that the event has been published.
+    pub fn remember(&self, key: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = Utc::now().timestamp();
+        let mut seen = self.seen.lock().unwrap();
+        seen.insert(key.to_string(), now);
+        seen.evict(self.capacity, now - self.ttl);
+        if let Some(journal) = &self.journal {
+            let mut journal = journal.lock().unwrap();
+            if let Err(e) = writeln!(journal.file, "{}\t{}", key, now) {
+                error!("Could not write to the deduplication journal: {}", e);
+            }
+            journal.lines += 1;
+            if journal.lines > seen.order.len() + self.capacity {
+                match Journal::create(journal.path.clone(), &seen.order) {
+                    Ok(compacted) => *journal = compacted,
+                    Err(e) => error!("Could not compact the deduplication journal: {}", e),
+                }
+            }
+        }
+    }
+
+    /// The number of duplicates that have been dropped.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn is_duplicate(dedup: &Dedup, key: &str) -> bool {
+        matches!(dedup.reserve(key), Claim::Duplicate)
+    }
+
+    #[test]
+    fn test_duplicate() {
+        // Arrange
+        let dedup = Dedup::new(10, 3600, false, None).unwrap();
+        // Act
+        let Claim::New(reservation) = dedup.reserve("111") else {
+            panic!("111 is new");
+        };
+        reservation.remember();
+        let after = is_duplicate(&dedup, "111");
+        // Assert
+        assert!(after);
+        assert!(!is_duplicate(&dedup, "222"));
+        assert_eq!(dedup.duplicates(), 1);
+    }
+
+    #[test]
+    fn test_reserve() {
+        // Arrange
+        let dedup = Dedup::new(10, 3600, false, None).unwrap();
+        // Act
+        let reservation = dedup.reserve("111");
+        let concurrent = dedup.reserve("111");
+        // Assert
+        assert!(matches!(reservation, Claim::New(_)));
+        assert!(matches!(concurrent, Claim::InFlight));
+        // Sending failed, a redelivery can be published.
+        drop(reservation);
+        assert!(matches!(dedup.reserve("111"), Claim::New(_)));
+        assert_eq!(dedup.duplicates(), 0);
+    }
+
+    #[test]
+    fn test_contains() {
+        let dedup = Dedup::new(10, 3600, false, None).unwrap();
+        dedup.remember("111:cloudevent");
+        assert!(dedup.contains("111:cloudevent"));
+        assert!(!dedup.contains("111"));
+        assert_eq!(dedup.duplicates(), 0);
+    }
+
+    #[test]
+    fn test_capacity() {
+        // Arrange
+        let dedup = Dedup::new(2, 3600, false, None).unwrap();
+        // Act
+        dedup.remember("1");
+        dedup.remember("2");
+        dedup.remember("3");
+        // Assert
+        assert!(!is_duplicate(&dedup, "1"));
+        assert!(is_duplicate(&dedup, "2"));
+        assert!(is_duplicate(&dedup, "3"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        // Arrange
+        let dedup = Dedup::new(0, 3600, false, None).unwrap();
+        // Act
+        dedup.remember("111");
+        // Assert
+        assert!(!is_duplicate(&dedup, "111"));
+        assert!(matches!(dedup.reserve("111"), Claim::New(_)));
+        assert!(matches!(dedup.reserve("111"), Claim::New(_)));
+    }
+
+    #[test]
+    fn test_journal() {
+        // Arrange
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let path = path.to_str().unwrap();
+        let dedup = Dedup::new(10, 3600, false, Some(path)).unwrap();
+        dedup.remember("111");
+        drop(dedup);
+        // Act
+        let dedup = Dedup::new(10, 3600, false, Some(path)).unwrap();
+        // Assert
+        assert!(is_duplicate(&dedup, "111"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_compacted() {
+        // Arrange
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let path = path.to_str().unwrap();
+        let dedup = Dedup::new(2, 3600, false, Some(path)).unwrap();
+        // Act
+        for key in ["1", "2", "3", "4", "5"] {
+            dedup.remember(key);
+        }
+        // Assert
+        let contents = fs::read_to_string(path).unwrap();
+        let keys: Vec<&str> = contents
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["4", "5"]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+use serde::Serialize;
+
+/// What happened to a single premis event of a request.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    /// Accepted and spooled, waiting to be published.
+    Queued,
+    Published {
+        /// The Pulsar message id, `{ledger_id}:{entry_id}:{partition}`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message_id: Option<String>,
+    },
+    /// Already published before, so it was not sent again.
+    Duplicate,
+    Failed {
+        reason: String,
+    },
+}
+
+impl Outcome {
+    /// The outcome without its details, as used in logs and metrics.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Queued => "queued",
+            Outcome::Published { .. } => "published",
+            Outcome::Duplicate => "duplicate",
+            Outcome::Failed { .. } => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EventResult {
+    pub identifier: String,
+    pub event_type: String,
+    pub topic: String,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::health::Check;
+use crate::metrics::METRICS;
+
+/// What was last seen of an event type.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LastSeen {
+    pub received_at: DateTime<Utc>,
+    /// The `eventDateTime` of the last event.
+    pub event_time: DateTime<Utc>,
+    /// Seconds between the `eventDateTime` and receiving the last event.
+    pub lag_seconds: f64,
+    pub events: u64,
+}
+
+/// The freshness of an event type, as reported on `/admin/freshness`.
+#[derive(Serialize, Debug, Clone)]
+pub struct FreshnessReport {
+    #[serde(flatten)]
+    pub last_seen: Option<LastSeen>,
+    pub threshold_seconds: Option<u64>,
+    pub stale: bool,
+}
+
+/// The event types without a threshold that are tracked, the event type is
+/// chosen by the client.
+const MAX_OTHER_EVENT_TYPES: usize = 100;
+
+/// The event types are in upper case.
+fn parse_thresholds(thresholds: Option<&str>) -> Result<HashMap<String, Duration>, String> {
+    thresholds
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|threshold| !threshold.is_empty())
+        .map(|threshold| {
+            threshold
+                .split_once('=')
+                .and_then(|(event_type, seconds)| {
+                    let seconds = seconds.trim().parse().ok()?;
+                    Some((
+                        event_type.trim().to_uppercase(),
+                        Duration::from_secs(seconds),
+                    ))
+                })
+                .ok_or_else(|| format!("Invalid freshness threshold '{}'.", threshold))
+        })
+        .collect()
+}
+
+/// Keeps track of when every event type was last received, so it shows
+/// when MediaHaven stops delivering webhooks.
+///
+/// An event type with a threshold is stale when none of its events arrived
+/// within the threshold, counting from the start of the bridge. The event
+/// types are compared case insensitively, they are reported in upper case.
+pub struct Freshness {
+    started_at: DateTime<Utc>,
+    thresholds: HashMap<String, Duration>,
+    last_seen: Mutex<HashMap<String, LastSeen>>,
+}
+
+impl Freshness {
+    /// * `thresholds` - Comma separated `{event_type}={seconds}` pairs.
+    pub fn new(thresholds: Option<&str>) -> Result<Self, String> {
+        Ok(Freshness {
+            started_at: Utc::now(),
+            thresholds: parse_thresholds(thresholds)?,
+            last_seen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The event types with a threshold.
+    pub fn event_types(&self) -> impl Iterator<Item = &str> {
+        self.thresholds.keys().map(String::as_str)
+    }
+
+    pub fn record(&self, event_type: &str, event_time: DateTime<Utc>, received_at: DateTime<Utc>) {
+        let lag_seconds = (received_at - event_time).num_milliseconds() as f64 / 1000.0;
+        let label = METRICS.event_type_label(event_type);
+        METRICS
+            .last_event_received
+            .with_label_values(&[label.as_str()])
+            .set(received_at.timestamp_millis() as f64 / 1000.0);
+        // Clocks that are out of sync should not show up as a negative lag.
+        METRICS
+            .delivery_lag
+            .with_label_values(&[label.as_str()])
+            .observe(lag_seconds.max(0.0));
+        let event_type = event_type.to_uppercase();
+        let mut last_seen = self.last_seen.lock().unwrap();
+        if !self.thresholds.contains_key(&event_type) && !last_seen.contains_key(&event_type) {
+            let others = last_seen
+                .keys()
+                .filter(|event_type| !self.thresholds.contains_key(*event_type))
+                .count();
+            if others >= MAX_OTHER_EVENT_TYPES {
+                return;
+            }
+        }
+        let events = last_seen.get(&event_type).map_or(0, |seen| seen.events);
+        last_seen.insert(
+            event_type,
+            LastSeen {
+                received_at,
+                event_time,
+                lag_seconds,
+                events: events + 1,
+            },
+        );
+    }
+
+    fn is_stale(
+        &self,
+        event_type: &str,
+        received_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        match self.thresholds.get(event_type) {
+            Some(threshold) => {
+                let since = received_at.unwrap_or(self.started_at);
+                (now - since).to_std().unwrap_or_default() > *threshold
+            }
+            None => false,
+        }
+    }
+
+    /// The freshness of every event type that was received or has a
+    /// threshold.
+    pub fn report(&self, now: DateTime<Utc>) -> BTreeMap<String, FreshnessReport> {
+        let last_seen = self.last_seen.lock().unwrap();
+        last_seen
+            .keys()
+            .chain(self.thresholds.keys())
+            .map(|event_type| {
+                let seen = last_seen.get(event_type).cloned();
+                let received_at = seen.as_ref().map(|seen| seen.received_at);
+                let report = FreshnessReport {
+                    stale: self.is_stale(event_type, received_at, now),
+                    last_seen: seen,
+                    threshold_seconds: self
+                        .thresholds
+                        .get(event_type)
+                        .map(|threshold| threshold.as_secs()),
+                };
+                (event_type.clone(), report)
+            })
+            .collect()
+    }
+
+    /// Degraded when an event type is stale.
+    pub fn check(&self, now: DateTime<Utc>) -> Check {
+        if self.thresholds.is_empty() {
+            return Check::Disabled;
+        }
+        let stale: Vec<String> = self
+            .report(now)
+            .into_iter()
+            .filter(|(_, report)| report.stale)
+            .map(|(event_type, _)| event_type)
+            .collect();
+        if stale.is_empty() {
+            Check::Ok
+        } else {
+            Check::Degraded {
+                reason: format!("No recent events of type {}.", stale.join(", ")),
+            }
+        }
+    }
+}
+
+/// When every event type was last received.
+pub async fn report_freshness(freshness: web::Data<Freshness>) -> HttpResponse {
+    HttpResponse::Ok().json(freshness.report(Utc::now()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_thresholds() {
+        let thresholds =
+            parse_thresholds(Some("FLOW.ARCHIVED=3600, RECORDS.UPDATED = 60")).unwrap();
+        assert_eq!(thresholds["FLOW.ARCHIVED"], Duration::from_secs(3600));
+        assert_eq!(thresholds["RECORDS.UPDATED"], Duration::from_secs(60));
+        let thresholds = parse_thresholds(Some("flow.archived=3600")).unwrap();
+        assert_eq!(thresholds["FLOW.ARCHIVED"], Duration::from_secs(3600));
+        assert!(parse_thresholds(Some("FLOW.ARCHIVED")).is_err());
+        assert!(parse_thresholds(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        // Arrange
+        let freshness = Freshness::new(Some("flow.archived=60,RECORDS.UPDATED=60")).unwrap();
+        let received_at = Utc::now();
+        freshness.record(
+            "FLOW.Archived",
+            received_at - chrono::Duration::seconds(5),
+            received_at,
+        );
+        // Act
+        let fresh = freshness.check(received_at + chrono::Duration::seconds(30));
+        let stale = freshness.check(received_at + chrono::Duration::seconds(90));
+        let report = freshness.report(received_at);
+        // Assert
+        assert_eq!(fresh, Check::Ok);
+        assert_eq!(
+            stale,
+            Check::Degraded {
+                reason: String::from("No recent events of type FLOW.ARCHIVED, RECORDS.UPDATED.")
+            }
+        );
+        let last_seen = report["FLOW.ARCHIVED"].last_seen.as_ref().unwrap();
+        assert_eq!(last_seen.lag_seconds, 5.0);
+        assert_eq!(last_seen.events, 1);
+        assert!(report["RECORDS.UPDATED"].last_seen.is_none());
+    }
+
+    #[test]
+    fn test_record_bounded() {
+        // Arrange
+        let freshness = Freshness::new(Some("FLOW.ARCHIVED=60")).unwrap();
+        let received_at = Utc::now();
+        // Act
+        for i in 0..MAX_OTHER_EVENT_TYPES + 10 {
+            freshness.record(&format!("MADE.UP.{}", i), received_at, received_at);
+        }
+        freshness.record("MADE.UP.0", received_at, received_at);
+        freshness.record("flow.archived", received_at, received_at);
+        // Assert
+        let report = freshness.report(received_at);
+        assert_eq!(report.len(), MAX_OTHER_EVENT_TYPES + 1);
+        assert_eq!(report["MADE.UP.0"].last_seen.as_ref().unwrap().events, 2);
+        assert!(!report.contains_key("MADE.UP.100"));
+        assert_eq!(
+            report["FLOW.ARCHIVED"].last_seen.as_ref().unwrap().events,
+            1
+        );
+    }
+
+    #[test]
+    fn test_check_disabled() {
+        assert_eq!(
+            Freshness::new(None).unwrap().check(Utc::now()),
+            Check::Disabled
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::{rt::time::timeout, web, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::freshness::Freshness;
+use crate::pulsar_client::PulsarConnection;
+use crate::spool::Spool;
+
+/// How long the broker gets to answer, within the 1 second timeout of the
+/// probes.
+const BROKER_CHECK_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// The status of a single dependency.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Check {
+    Ok,
+    /// Not configured, so it can not fail.
+    Disabled,
+    /// Working, but something needs attention. It does not make the bridge
+    /// unhealthy.
+    Degraded {
+        reason: String,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+impl Check {
+    fn from_result<E: ToString>(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Check::Ok,
+            Err(e) => Check::Failed {
+                reason: e.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+/// Checks the dependencies of the bridge for the readiness and startup
+/// probes.
+///
+/// Checking the broker means a round trip to it, so a report is reused for
+/// `probe_interval` and concurrent probes wait for the same check.
+pub struct Health {
+    probe_interval: Duration,
+    spool_dir: Option<String>,
+    freshness: Arc<Freshness>,
+    cached: Mutex<Option<(Instant, HealthReport)>>,
+    // Held while checking, so only one check runs at a time.
+    checking: tokio::sync::Mutex<()>,
+    started: AtomicBool,
+}
+
+impl Health {
+    pub fn new(
+        probe_interval: Duration,
+        spool_dir: Option<String>,
+        freshness: Arc<Freshness>,
+    ) -> Self {
+        Health {
+            probe_interval,
+            spool_dir,
+            freshness,
+            cached: Mutex::new(None),
+            checking: tokio::sync::Mutex::new(()),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    fn cached_report(&self) -> Option<HealthReport> {
+        match self.cached.lock().unwrap().as_ref() {
+            Some((checked_at, report)) if checked_at.elapsed() < self.probe_interval => {
+                Some(report.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub async fn report(&self, pulsar_connection: &PulsarConnection) -> HealthReport {
+        if let Some(report) = self.cached_report() {
+            return report;
+        }
+        let _checking = self.checking.lock().await;
+        // Checked by another probe meanwhile.
+        if let Some(report) = self.cached_report() {
+            return report;
+        }
+        let mut checks = BTreeMap::new();
+        // Getting this far means the configuration could be parsed.
+        checks.insert("config", Check::Ok);
+        let broker = match timeout(BROKER_CHECK_TIMEOUT, pulsar_connection.check_connection()).await
+        {
+            Ok(result) => Check::from_result(result),
+            Err(_) => Check::Failed {
+                reason: format!(
+                    "The broker did not answer within {} ms",
+                    BROKER_CHECK_TIMEOUT.as_millis()
+                ),
+            },
+        };
+        checks.insert("broker", broker);
+        checks.insert(
+            "spool",
+            match &self.spool_dir {
+                Some(dir) => Check::from_result(Spool::new(dir).and_then(|spool| spool.check())),
+                None => Check::Disabled,
+            },
+        );
+        checks.insert("freshness", self.freshness.check(Utc::now()));
+        let healthy = checks
+            .values()
+            .all(|check| !matches!(check, Check::Failed { .. }));
+        let report = HealthReport { healthy, checks };
+        if healthy {
+            self.started.store(true, Ordering::Relaxed);
+        }
+        *self.cached.lock().unwrap() = Some((Instant::now(), report.clone()));
+        report
+    }
+
+    /// Whether all the dependencies have been healthy at least once.
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::Relaxed)
+    }
+}
+
+/// Ready when all the dependencies are healthy.
+pub async fn readyz(
+    health: web::Data<Health>,
+    pulsar_connection: web::Data<PulsarConnection>,
+) -> HttpResponse {
+    let report = health.report(&pulsar_connection).await;
+    if report.healthy {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Started once all the dependencies have been healthy, it stays that way
+/// after. The readiness probe takes over from there.
+pub async fn startupz(
+    health: web::Data<Health>,
+    pulsar_connection: web::Data<PulsarConnection>,
+) -> HttpResponse {
+    let report = health.report(&pulsar_connection).await;
+    if health.is_started() {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_report_not_connected() {
+        // Arrange
+        let health = Health::new(
+            Duration::from_secs(5),
+            None,
+            Arc::new(Freshness::new(None).unwrap()),
+        );
+        let pulsar_connection = PulsarConnection::default();
+        // Act
+        let report = health.report(&pulsar_connection).await;
+        // Assert
+        assert!(!report.healthy);
+        assert_eq!(report.checks["config"], Check::Ok);
+        assert_eq!(report.checks["spool"], Check::Disabled);
+        assert_eq!(report.checks["freshness"], Check::Disabled);
+        assert_eq!(
+            report.checks["broker"],
+            Check::Failed {
+                reason: String::from("Not connected to Pulsar")
+            }
+        );
+        assert!(!health.is_started());
+    }
+
+    #[actix_web::test]
+    async fn test_report_spool_failed() {
+        // Arrange
+        let health = Health::new(
+            Duration::from_secs(5),
+            Some(String::from("/proc/mh-events2pulsar")),
+            Arc::new(Freshness::new(None).unwrap()),
+        );
+        let pulsar_connection = PulsarConnection::default();
+        // Act
+        let report = health.report(&pulsar_connection).await;
+        // Assert
+        assert!(matches!(report.checks["spool"], Check::Failed { .. }));
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::metrics::METRICS;
+
+/// A premis event that has been handed to Pulsar but is not acknowledged yet.
+#[derive(Debug, Clone)]
+pub struct PendingEvent {
+    pub topic: String,
+    pub premis_event_xml: String,
+}
+
+/// Keeps track of the events that are being sent to Pulsar.
+///
+/// An event is registered before it is sent and completed once the broker
+/// replied. If the request handler gets dropped halfway, e.g. because the
+/// shutdown timeout expired, the event stays behind and can be reported.
+#[derive(Default)]
+pub struct InFlight {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, PendingEvent>>,
+}
+
+impl InFlight {
+    pub fn register(&self, topic: &str, premis_event_xml: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.pending.lock().unwrap().insert(
+            id,
+            PendingEvent {
+                topic: topic.to_string(),
+                premis_event_xml: premis_event_xml.to_string(),
+            },
+        );
+        METRICS.events_in_flight.inc();
+        id
+    }
+
+    pub fn complete(&self, id: u64) {
+        if self.pending.lock().unwrap().remove(&id).is_some() {
+            METRICS.events_in_flight.dec();
+        }
+    }
+
+    /// Take all the events that were never completed.
+    pub fn drain(&self) -> Vec<PendingEvent> {
+        let pending: Vec<PendingEvent> = self
+            .pending
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, pending_event)| pending_event)
+            .collect();
+        METRICS.events_in_flight.sub(pending.len() as i64);
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain_returns_uncompleted_events() {
+        // Arrange
+        let in_flight = InFlight::default();
+        let first = in_flight.register("be.mediahaven.flow.archived", "<first/>");
+        in_flight.register("be.mediahaven.records.update", "<second/>");
+        // Act
+        in_flight.complete(first);
+        let pending = in_flight.drain();
+        // Assert
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].topic, "be.mediahaven.records.update");
+        assert_eq!(pending[0].premis_event_xml, "<second/>");
+        assert!(in_flight.drain().is_empty());
+    }
+}
@@ -1,11 +1,18 @@
 use chrono::{DateTime, Utc};
-use std::str;
+use std::collections::{HashMap, HashSet};
+use std::{env, fs, str};
 
 use quick_xml::de::from_str;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+use xmltree::{Element, Namespace, XMLNode};
+
+pub mod rdf;
+
+use rdf::RdfFormat;
 
 // Config
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(default = "default_pulsar_host")]
     pub pulsar_host: String,
@@ -13,6 +20,161 @@ pub struct Config {
     pub pulsar_port: String,
     #[serde(default = "default_pulsar_namespace")]
     pub pulsar_namespace: String,
+    /// Template of the producer name, see [`Config::producer_name`].
+    #[serde(default = "default_pulsar_producer_name")]
+    pub pulsar_producer_name: String,
+    /// Whether broker-side deduplication is enabled, which requires a
+    /// producer name that is stable across restarts.
+    #[serde(default)]
+    pub pulsar_deduplication: bool,
+    /// Seconds after which the producer of a topic without events is closed, 0 keeps them open.
+    #[serde(default = "default_pulsar_producer_idle_timeout")]
+    pub pulsar_producer_idle_timeout: u64,
+    /// Maximum number of producers, the least recently used one is closed to make room.
+    #[serde(default = "default_pulsar_max_producers")]
+    pub pulsar_max_producers: usize,
+    /// The CloudEvents `source` of the messages, a URI-reference.
+    #[serde(default = "default_cloudevents_source")]
+    pub cloudevents_source: String,
+    /// How the CloudEvents are put in the messages.
+    #[serde(default = "default_cloudevents_mode")]
+    pub cloudevents_mode: ContentMode,
+    /// Comma separated `{topic}={mode}` pairs overriding `cloudevents_mode`.
+    pub cloudevents_topic_modes: Option<String>,
+    /// What goes in the `data` of a structured mode message.
+    #[serde(default = "default_cloudevents_data")]
+    pub cloudevents_data: CloudEventsData,
+    /// Comma separated topics whose events are also published as RDF, on `{topic}.rdf`.
+    pub rdf_topics: Option<String>,
+    #[serde(default = "default_rdf_format")]
+    pub rdf_format: RdfFormat,
+    /// Milliseconds to wait before the first reconnect to Pulsar, doubled on every failure.
+    #[serde(default = "default_pulsar_reconnect_min_backoff")]
+    pub pulsar_reconnect_min_backoff: u64,
+    /// Upper bound in milliseconds of the wait between reconnects to Pulsar.
+    #[serde(default = "default_pulsar_reconnect_max_backoff")]
+    pub pulsar_reconnect_max_backoff: u64,
+    /// Comma separated addresses to listen on, e.g. `0.0.0.0,::1`.
+    #[serde(default = "default_bind_addresses")]
+    pub bind_addresses: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Number of HTTP workers, the number of CPUs if unset.
+    pub workers: Option<usize>,
+    /// Seconds an idle connection is kept open, 0 disables keep-alive.
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u64,
+    /// Milliseconds a client gets to send the request headers, 0 disables the timeout.
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+    /// Maximum size in bytes of a request body. Compressed bodies are held to
+    /// it after decompression, which stops decompression bombs early.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+    /// PEM certificate chain to serve HTTPS with, together with `tls_key`.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub tls_cert: Option<String>,
+    /// PEM private key of `tls_cert`.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub tls_key: Option<String>,
+    /// PEM bundle of certificate authorities to verify client certificates against.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub tls_client_ca: Option<String>,
+    /// Seconds between checks whether the certificate files changed, 0 disables reloading.
+    #[serde(default = "default_tls_reload_interval")]
+    pub tls_reload_interval: u64,
+    /// Comma separated CIDR ranges allowed to call `/events`, everyone if unset.
+    pub allowed_ips: Option<String>,
+    /// Comma separated CIDR ranges of proxies whose forwarding headers are trusted.
+    pub trusted_proxies: Option<String>,
+    /// The forwarding header set by the trusted proxies, e.g. `X-Forwarded-For`
+    /// or `Forwarded`. Other forwarding headers are ignored.
+    #[serde(default = "default_trusted_proxy_header")]
+    pub trusted_proxy_header: String,
+    /// How `/events` requests are authenticated.
+    #[serde(default = "default_auth_mode")]
+    pub auth_mode: AuthMode,
+    /// Comma separated secrets, all of them are accepted.
+    pub auth_secrets: Option<String>,
+    /// File with a secret per line, in addition to `auth_secrets`.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub auth_secrets_file: Option<String>,
+    /// Header with the HMAC signature of the body as sent, before it is decompressed.
+    #[serde(default = "default_auth_hmac_header")]
+    pub auth_hmac_header: String,
+    /// Header with the unix timestamp that is signed along with the body.
+    #[serde(default = "default_auth_hmac_timestamp_header")]
+    pub auth_hmac_timestamp_header: String,
+    /// Seconds the timestamp may differ from now, 0 signs the body only.
+    #[serde(default = "default_auth_hmac_max_skew")]
+    pub auth_hmac_max_skew: u64,
+    /// Comma separated bearer tokens for the `/admin` endpoints, which are
+    /// not served without one.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub admin_token: Option<String>,
+    /// Number of published events remembered to drop redeliveries, 0 disables it.
+    #[serde(default = "default_dedup_capacity")]
+    pub dedup_capacity: usize,
+    /// Seconds a published event is remembered.
+    #[serde(default = "default_dedup_ttl")]
+    pub dedup_ttl: u64,
+    /// Whether a redelivery also needs the same premis XML to be a duplicate.
+    #[serde(default)]
+    pub dedup_content_hash: bool,
+    /// File to keep the remembered events in across restarts.
+    pub dedup_journal: Option<String>,
+    /// Directory to archive the raw `/events` requests in, disabled if unset.
+    pub audit_dir: Option<String>,
+    /// Size in bytes after which an audit file is rolled.
+    #[serde(default = "default_audit_max_file_size")]
+    pub audit_max_file_size: u64,
+    /// Seconds after which an audit file is rolled.
+    #[serde(default = "default_audit_max_file_age")]
+    pub audit_max_file_age: u64,
+    /// Days to keep the rolled audit files.
+    #[serde(default = "default_audit_retention_days")]
+    pub audit_retention_days: u64,
+    /// Comma separated `{event_type}={seconds}` pairs. The health check is
+    /// degraded when no events of a type arrived within its window.
+    pub freshness_thresholds: Option<String>,
+    /// Comma separated event types with their own metric labels, the other
+    /// event types are counted as `other`. The event types of the freshness
+    /// thresholds are added.
+    #[serde(default = "default_metrics_event_types")]
+    pub metrics_event_types: String,
+    /// Seconds a health check result is reused by the probes.
+    #[serde(default = "default_health_probe_interval")]
+    pub health_probe_interval: u64,
+    /// Seconds to wait for in-flight requests to finish when shutting down.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Directory where events that could not be sent to Pulsar are written to.
+    /// They are published again on the next start.
+    pub spool_dir: Option<String>,
+    /// Answer `/events` with `202 Accepted` once the events are spooled and
+    /// publish them in the background. Requires `spool_dir`.
+    #[serde(default)]
+    pub accept_async: bool,
+    /// Seconds the status of an accepted request is kept in memory.
+    #[serde(default = "default_request_status_ttl")]
+    pub request_status_ttl: u64,
+    /// Where the OpenTelemetry spans are exported to.
+    #[serde(default = "default_trace_exporter")]
+    pub trace_exporter: TraceExporter,
+    /// File the spans are appended to with the `file` exporter.
+    pub trace_file: Option<String>,
+    /// How the log lines are written.
+    #[serde(default = "default_log_format")]
+    pub log_format: LogFormat,
+    /// Bytes of a request body that are logged at debug level, 0 to not log
+    /// the bodies.
+    #[serde(default = "default_log_payload_max_length")]
+    pub log_payload_max_length: usize,
+}
+
+/// An empty variable, as in `.env.example`, is the same as an unset one.
+fn empty_as_none<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.filter(|value| !value.is_empty()))
 }
 
 fn default_pulsar_host() -> String {
@@ -23,10 +185,566 @@ fn default_pulsar_port() -> String {
     String::from("6650")
 }
 
+fn default_cloudevents_source() -> String {
+    String::from("urn:meemoo:mh-events2pulsar")
+}
+
+fn default_cloudevents_mode() -> ContentMode {
+    ContentMode::Structured
+}
+
+fn default_cloudevents_data() -> CloudEventsData {
+    CloudEventsData::Premis
+}
+
+fn default_rdf_format() -> RdfFormat {
+    RdfFormat::JsonLd
+}
+
 fn default_pulsar_namespace() -> String {
     String::from("default")
 }
 
+fn default_pulsar_producer_name() -> String {
+    String::from("mh-events2pulsar-{hostname}")
+}
+
+fn default_pulsar_producer_idle_timeout() -> u64 {
+    300
+}
+
+fn default_pulsar_max_producers() -> usize {
+    100
+}
+
+fn default_pulsar_reconnect_min_backoff() -> u64 {
+    500
+}
+
+fn default_pulsar_reconnect_max_backoff() -> u64 {
+    30000
+}
+
+fn default_bind_addresses() -> String {
+    String::from("0.0.0.0")
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_keep_alive() -> u64 {
+    5
+}
+
+fn default_request_timeout() -> u64 {
+    5000
+}
+
+fn default_max_body_size() -> usize {
+    // 1 MB.
+    1_000_000
+}
+
+fn default_tls_reload_interval() -> u64 {
+    60
+}
+
+fn default_trusted_proxy_header() -> String {
+    String::from("X-Forwarded-For")
+}
+
+fn default_auth_mode() -> AuthMode {
+    AuthMode::None
+}
+
+fn default_auth_hmac_header() -> String {
+    String::from("X-Signature")
+}
+
+fn default_auth_hmac_timestamp_header() -> String {
+    String::from("X-Timestamp")
+}
+
+fn default_auth_hmac_max_skew() -> u64 {
+    300
+}
+
+fn default_dedup_capacity() -> usize {
+    0
+}
+
+fn default_dedup_ttl() -> u64 {
+    // A day.
+    86400
+}
+
+fn default_audit_max_file_size() -> u64 {
+    // 100 MB.
+    100_000_000
+}
+
+fn default_audit_max_file_age() -> u64 {
+    // An hour.
+    3600
+}
+
+fn default_audit_retention_days() -> u64 {
+    90
+}
+
+fn default_health_probe_interval() -> u64 {
+    5
+}
+
+fn default_shutdown_timeout() -> u64 {
+    // Stay below the default termination grace period of 30 seconds.
+    25
+}
+
+fn default_request_status_ttl() -> u64 {
+    3600
+}
+
+fn default_metrics_event_types() -> String {
+    String::from("FLOW.ARCHIVED,RECORDS.UPDATE,RECORDS.DIRECT_DOWNLOAD.ACCESS")
+}
+
+fn default_trace_exporter() -> TraceExporter {
+    TraceExporter::None
+}
+
+fn default_log_format() -> LogFormat {
+    LogFormat::Json
+}
+
+fn default_log_payload_max_length() -> usize {
+    1024
+}
+
+/// How the webhook calls of MediaHaven are authenticated.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    None,
+    /// HTTP Basic, the secrets are `user:password` pairs.
+    Basic,
+    /// `Authorization: Bearer {token}`.
+    Bearer,
+    /// A hex encoded HMAC-SHA256 signature of the body.
+    Hmac,
+}
+
+/// Where the OpenTelemetry spans are exported to.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceExporter {
+    None,
+    /// OTLP over HTTP, see the `OTEL_EXPORTER_OTLP_*` environment variables.
+    Otlp,
+    Stdout,
+    /// JSON lines in `trace_file`.
+    File,
+}
+
+/// How the log lines are written.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// A JSON object per line, with the fields of the request and event.
+    Json,
+    Text,
+}
+
+/// How the CloudEvents are put in the Pulsar messages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentMode {
+    /// The whole event as JSON in the payload.
+    Structured,
+    /// The premis XML as the payload and the attributes as `ce_` properties.
+    Binary,
+}
+
+/// The renderings of the premis event in the `data` of the CloudEvents.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CloudEventsData {
+    /// The XML in `data.premis`.
+    Premis,
+    /// The JSON of [`Event::to_json`] in `data.event`.
+    Event,
+    /// Both `data.premis` and `data.event`.
+    Both,
+}
+
+impl Config {
+    /// The topics that do not use the `cloudevents_mode`.
+    pub fn cloudevents_topic_modes(&self) -> Result<HashMap<String, ContentMode>, String> {
+        self.cloudevents_topic_modes
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|topic_mode| !topic_mode.is_empty())
+            .map(|topic_mode| {
+                let mode = match topic_mode.split_once('=') {
+                    Some((topic, "structured")) => Some((topic, ContentMode::Structured)),
+                    Some((topic, "binary")) => Some((topic, ContentMode::Binary)),
+                    _ => None,
+                };
+                mode.map(|(topic, mode)| (topic.trim().to_string(), mode))
+                    .ok_or_else(|| format!("Invalid CloudEvents topic mode '{}'.", topic_mode))
+            })
+            .collect()
+    }
+
+    /// The topics whose events are also published as RDF.
+    pub fn rdf_topics(&self) -> HashSet<String> {
+        self.rdf_topics
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|topic| !topic.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The event types with their own metric labels.
+    pub fn metrics_event_types(&self) -> Vec<&str> {
+        self.metrics_event_types
+            .split(',')
+            .map(str::trim)
+            .filter(|event_type| !event_type.is_empty())
+            .collect()
+    }
+
+    /// The name of the Pulsar producer.
+    ///
+    /// Pulsar requires the producer name to be unique per topic, so every
+    /// replica needs its own. The `pulsar_producer_name` template supports:
+    ///
+    /// * `{hostname}` - The hostname, which is the pod name on OpenShift.
+    /// * `{pod_name}` - The `POD_NAME` environment variable, or the hostname if unset.
+    /// * `{random}` - A random suffix, which is not allowed with deduplication.
+    pub fn producer_name(&self) -> Result<String, String> {
+        let hostname = hostname();
+        let pod_name = env::var("POD_NAME").unwrap_or_else(|_| hostname.clone());
+        let random = Uuid::new_v4().to_simple().to_string()[..8].to_string();
+        self.render_producer_name(&hostname, &pod_name, &random)
+    }
+
+    fn render_producer_name(
+        &self,
+        hostname: &str,
+        pod_name: &str,
+        random: &str,
+    ) -> Result<String, String> {
+        let template = &self.pulsar_producer_name;
+        if self.pulsar_deduplication && template.contains("{random}") {
+            return Err(format!(
+                "The producer name '{}' has to be stable when deduplication is enabled, remove '{{random}}'.",
+                template
+            ));
+        }
+        let name = template
+            .replace("{hostname}", hostname)
+            .replace("{pod_name}", pod_name)
+            .replace("{random}", random);
+        if name.is_empty() {
+            return Err(String::from("The producer name can not be empty."));
+        }
+        Ok(name)
+    }
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| String::from("localhost"))
+}
+
+fn is_valid_event_type(event_type: &str) -> bool {
+    event_type
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// The topic of the events of a type, see [`Event::topic`].
+pub fn event_type_topic(event_type: &str) -> String {
+    format!("be.mediahaven.{}", event_type.to_lowercase())
+}
+
+const PREMIS_PREFIX: &str = "premis";
+const PREMIS_NAMESPACE: &str = "info:lc/xmlns/premis-v2";
+
+fn premis_element(name: &str, namespace: &str) -> Element {
+    let mut element = Element::new(name);
+    element.prefix = Some(PREMIS_PREFIX.to_string());
+    element.namespace = Some(namespace.to_string());
+    element
+}
+
+/// Premis elements that can occur more than once, they are always arrays in
+/// the JSON of an event. Any other element that is repeated is an array too.
+const REPEATED_PREMIS_ELEMENTS: [&str; 9] = [
+    "eventDetailInformation",
+    "eventDetailExtension",
+    "eventOutcomeInformation",
+    "eventOutcomeDetail",
+    "eventOutcomeDetailExtension",
+    "linkingAgentIdentifier",
+    "linkingAgentRole",
+    "linkingObjectIdentifier",
+    "linkingObjectRole",
+];
+
+/// The premis elements in the order of the premis 2 and 3 schemas, so the
+/// XML generated from JSON follows it.
+const PREMIS_ELEMENT_ORDER: [&str; 21] = [
+    "eventIdentifier",
+    "eventIdentifierType",
+    "eventIdentifierValue",
+    "eventType",
+    "eventDateTime",
+    "eventDetail",
+    "eventDetailInformation",
+    "eventDetailExtension",
+    "eventOutcomeInformation",
+    "eventOutcome",
+    "eventOutcomeDetail",
+    "eventOutcomeDetailNote",
+    "eventOutcomeDetailExtension",
+    "linkingAgentIdentifier",
+    "linkingAgentIdentifierType",
+    "linkingAgentIdentifierValue",
+    "linkingAgentRole",
+    "linkingObjectIdentifier",
+    "linkingObjectIdentifierType",
+    "linkingObjectIdentifierValue",
+    "linkingObjectRole",
+];
+
+/// The keys of the JSON of a premis element that are not child elements.
+const ATTRIBUTES_KEY: &str = "@attributes";
+const TEXT_KEY: &str = "#text";
+const EXTENSIONS_KEY: &str = "extensions";
+
+/// The text of an element, without the surrounding whitespace.
+fn element_text(element: &Element) -> Option<String> {
+    let text: String = element
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            XMLNode::Text(text) | XMLNode::CData(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn attributes_to_json(element: &Element) -> serde_json::Value {
+    element
+        .attributes
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone().into()))
+        .collect::<serde_json::Map<String, serde_json::Value>>()
+        .into()
+}
+
+/// A premis element as JSON: the text of a leaf, or an object keyed by the
+/// names of the children. Its attributes go in `@attributes` and, next to
+/// children or attributes, its text in `#text`. Children from other
+/// namespaces go in `extensions`.
+fn premis_to_json(element: &Element, namespace: Option<&str>) -> serde_json::Value {
+    let children: Vec<&Element> = element
+        .children
+        .iter()
+        .filter_map(XMLNode::as_element)
+        .collect();
+    let text = element_text(element);
+    if children.is_empty() && element.attributes.is_empty() {
+        return serde_json::Value::String(text.unwrap_or_default());
+    }
+    let mut object = serde_json::Map::new();
+    if !element.attributes.is_empty() {
+        object.insert(String::from(ATTRIBUTES_KEY), attributes_to_json(element));
+    }
+    if let Some(text) = text {
+        object.insert(String::from(TEXT_KEY), text.into());
+    }
+    let mut extensions = Vec::new();
+    for child in &children {
+        if child.namespace.as_deref() != namespace {
+            extensions.push(extension_to_json(child));
+            continue;
+        }
+        let value = premis_to_json(child, namespace);
+        let repeated = REPEATED_PREMIS_ELEMENTS.contains(&child.name.as_str())
+            || children
+                .iter()
+                .filter(|sibling| {
+                    sibling.name == child.name && sibling.namespace == child.namespace
+                })
+                .count()
+                > 1;
+        if repeated {
+            if let serde_json::Value::Array(values) = object
+                .entry(child.name.clone())
+                .or_insert_with(|| serde_json::Value::Array(Vec::new()))
+            {
+                values.push(value);
+            }
+        } else {
+            object.insert(child.name.clone(), value);
+        }
+    }
+    if !extensions.is_empty() {
+        object.insert(
+            String::from(EXTENSIONS_KEY),
+            serde_json::Value::Array(extensions),
+        );
+    }
+    serde_json::Value::Object(object)
+}
+
+/// The premis element `name` of the JSON made by [`premis_to_json`].
+fn json_to_premis(
+    name: &str,
+    value: &serde_json::Value,
+    namespace: &str,
+) -> Result<Element, String> {
+    let mut element = premis_element(name, namespace);
+    let object = match value {
+        serde_json::Value::String(text) => {
+            if !text.is_empty() {
+                element.children.push(XMLNode::Text(text.clone()));
+            }
+            return Ok(element);
+        }
+        serde_json::Value::Object(object) => object,
+        _ => {
+            return Err(format!(
+                "The field '{}' must be a string or an object.",
+                name
+            ))
+        }
+    };
+    let mut children = Vec::new();
+    let mut extensions = Vec::new();
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            (ATTRIBUTES_KEY, serde_json::Value::Object(attributes)) => {
+                for (attribute, value) in attributes {
+                    let value = value.as_str().ok_or_else(|| {
+                        format!(
+                            "The attribute '{}' of '{}' must be a string.",
+                            attribute, name
+                        )
+                    })?;
+                    element
+                        .attributes
+                        .insert(attribute.clone(), value.to_string());
+                }
+            }
+            (TEXT_KEY, serde_json::Value::String(text)) => {
+                element.children.push(XMLNode::Text(text.clone()));
+            }
+            (EXTENSIONS_KEY, serde_json::Value::Array(values)) => {
+                for value in values {
+                    extensions.push(json_to_extension(value)?);
+                }
+            }
+            (ATTRIBUTES_KEY | TEXT_KEY | EXTENSIONS_KEY, _) => {
+                return Err(format!("Invalid '{}' in '{}'.", key, name));
+            }
+            (_, serde_json::Value::Array(values)) => {
+                for value in values {
+                    children.push(json_to_premis(key, value, namespace)?);
+                }
+            }
+            _ => children.push(json_to_premis(key, value, namespace)?),
+        }
+    }
+    // Stable, so the repeated elements keep their order.
+    children.sort_by_key(|child| {
+        PREMIS_ELEMENT_ORDER
+            .iter()
+            .position(|name| *name == child.name)
+            .unwrap_or(PREMIS_ELEMENT_ORDER.len())
+    });
+    element
+        .children
+        .extend(children.into_iter().chain(extensions).map(XMLNode::Element));
+    Ok(element)
+}
+
+/// An element of an extension, e.g. MediaHaven metadata, with its namespace
+/// so nothing is lost.
+fn extension_to_json(element: &Element) -> serde_json::Value {
+    let mut object = serde_json::Map::new();
+    object.insert(String::from("name"), element.name.clone().into());
+    if let Some(namespace) = &element.namespace {
+        object.insert(String::from("namespace"), namespace.clone().into());
+    }
+    if !element.attributes.is_empty() {
+        object.insert(String::from("attributes"), attributes_to_json(element));
+    }
+    if let Some(text) = element_text(element) {
+        object.insert(String::from("text"), text.into());
+    }
+    let children: Vec<serde_json::Value> = element
+        .children
+        .iter()
+        .filter_map(XMLNode::as_element)
+        .map(extension_to_json)
+        .collect();
+    if !children.is_empty() {
+        object.insert(String::from("children"), children.into());
+    }
+    serde_json::Value::Object(object)
+}
+
+/// The element of an extension made by [`extension_to_json`].
+fn json_to_extension(value: &serde_json::Value) -> Result<Element, String> {
+    let invalid = || format!("Invalid extension: {}", value);
+    let name = value["name"].as_str().ok_or_else(invalid)?;
+    let mut element = Element::new(name);
+    if let Some(namespace) = value.get("namespace") {
+        let namespace = namespace.as_str().ok_or_else(invalid)?;
+        let mut namespaces = Namespace::empty();
+        // The default namespace, the extensions have no prefix.
+        namespaces.put("", namespace);
+        element.namespace = Some(namespace.to_string());
+        element.namespaces = Some(namespaces);
+    }
+    if let Some(attributes) = value.get("attributes") {
+        for (attribute, value) in attributes.as_object().ok_or_else(invalid)? {
+            let value = value.as_str().ok_or_else(invalid)?;
+            element
+                .attributes
+                .insert(attribute.clone(), value.to_string());
+        }
+    }
+    if let Some(text) = value.get("text") {
+        let text = text.as_str().ok_or_else(invalid)?;
+        element.children.push(XMLNode::Text(text.to_string()));
+    }
+    if let Some(children) = value.get("children") {
+        for child in children.as_array().ok_or_else(invalid)? {
+            element
+                .children
+                .push(XMLNode::Element(json_to_extension(child)?));
+        }
+    }
+    Ok(element)
+}
+
 // XML structs
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Event {
@@ -39,7 +757,7 @@ pub struct Event {
     #[serde(rename = "eventDetail")]
     event_detail: Option<String>,
     #[serde(rename = "eventOutcomeInformation")]
-    event_outcome_information: EventOutcomeInformation,
+    event_outcome_information: Vec<EventOutcomeInformation>,
     #[serde(rename = "linkingAgentIdentifier")]
     linking_agent_identifier: Vec<LinkingAgentIdentifier>,
     #[serde(rename = "linkingObjectIdentifier")]
@@ -79,12 +797,129 @@ pub struct LinkingObjectIdentifier {
 }
 
 impl Event {
+    /// Panics when the body is not a valid premis event, see [`Event::try_new`].
     pub fn new(body: &str) -> Event {
+        Event::try_new(body).unwrap()
+    }
+
+    /// Parse a premis event, failing when it is not valid.
+    ///
+    /// The event type ends up in the Pulsar topic and the spool file names,
+    /// so it may only hold letters, digits, `.`, `_` and `-`.
+    pub fn try_new(body: &str) -> Result<Event, String> {
         // Deserialize XML to struct
-        let mut event: Event = from_str(body).unwrap();
+        let mut event: Event = from_str(body).map_err(|e| e.to_string())?;
+        if event.event_type.trim().is_empty() {
+            return Err(String::from("The field 'eventType' can not be empty."));
+        }
+        if !is_valid_event_type(&event.event_type) {
+            return Err(format!("Invalid event type '{}'.", event.event_type));
+        }
         // Add the body XMl as payload to the struct
         event.event_payload = body.to_string();
-        event
+        Ok(event)
+    }
+
+    /// Create an event from its JSON representation, see
+    /// [`Event::to_json`].
+    ///
+    /// The premis XML is generated from the JSON, in its `namespace` or else
+    /// premis 2, so the JSON of an event gives back the same event.
+    pub fn from_json(value: serde_json::Value) -> Result<Event, String> {
+        let namespace = match value.get("namespace") {
+            Some(namespace) => namespace
+                .as_str()
+                .ok_or("The field 'namespace' must be a string.")?,
+            None => PREMIS_NAMESPACE,
+        };
+        let mut content = value.clone();
+        if let Some(object) = content.as_object_mut() {
+            object.remove("namespace");
+        }
+        let mut event_element = json_to_premis("event", &content, namespace)?;
+        let mut namespaces = Namespace::empty();
+        namespaces.put(PREMIS_PREFIX, namespace);
+        event_element.namespaces = Some(namespaces);
+        let mut xml = Vec::new();
+        event_element.write(&mut xml).map_err(|e| e.to_string())?;
+        let event = Event::try_new(&String::from_utf8(xml).expect("xmltree writes UTF-8"))?;
+        let mut required = vec![
+            (
+                "eventIdentifierType",
+                &event.event_identifier.event_identifier_type,
+            ),
+            (
+                "eventIdentifierValue",
+                &event.event_identifier.event_identifier_value,
+            ),
+        ];
+        for outcome in &event.event_outcome_information {
+            required.push(("eventOutcome", &outcome.event_outcome));
+        }
+        for (name, value) in required {
+            if value.trim().is_empty() {
+                return Err(format!("The field '{}' can not be empty.", name));
+            }
+        }
+        // The premis XML would not parse without them.
+        if event.event_outcome_information.is_empty() {
+            return Err(String::from(
+                "At least one 'eventOutcomeInformation' is required.",
+            ));
+        }
+        if event.linking_agent_identifier.is_empty() {
+            return Err(String::from(
+                "At least one 'linkingAgentIdentifier' is required.",
+            ));
+        }
+        if event.linking_object_identifier.is_empty() {
+            return Err(String::from(
+                "At least one 'linkingObjectIdentifier' is required.",
+            ));
+        }
+        Ok(event)
+    }
+
+    /// The premis event as JSON, see `docs/premis-event.schema.json`.
+    ///
+    /// The premis elements are keyed by their names, as in
+    /// [`Event::from_json`], with their attributes in `@attributes`.
+    /// Elements from other namespaces are kept in the `extensions` of their
+    /// parent.
+    pub fn to_json(&self) -> Result<serde_json::Value, String> {
+        let event = Element::parse(self.event_payload.as_bytes()).map_err(|e| e.to_string())?;
+        let mut json = premis_to_json(&event, event.namespace.as_deref());
+        if let (serde_json::Value::Object(object), Some(namespace)) = (&mut json, &event.namespace)
+        {
+            object.insert(String::from("namespace"), namespace.clone().into());
+        }
+        Ok(json)
+    }
+
+    /// The topic part in: persistent://{tenant}/{namespace}/{topic}.
+    pub fn topic(&self) -> String {
+        event_type_topic(&self.event_type)
+    }
+
+    /// The value of the event identifier, e.g. the `MEDIAHAVEN_EVENT` id.
+    pub fn identifier(&self) -> &str {
+        &self.event_identifier.event_identifier_value
+    }
+
+    /// The event outcomes, e.g. `OK`.
+    pub fn outcomes(&self) -> Vec<&str> {
+        self.event_outcome_information
+            .iter()
+            .map(|outcome| outcome.event_outcome.as_str())
+            .collect()
+    }
+
+    /// The values of the linking agent identifiers, e.g. the MediaHaven users.
+    pub fn agents(&self) -> Vec<&str> {
+        self.linking_agent_identifier
+            .iter()
+            .map(|agent| agent.linking_agent_identifier_value.as_str())
+            .collect()
     }
 
     pub fn to_xml(&self) -> String {
@@ -107,6 +942,136 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+
+    fn config(pulsar_producer_name: &str, pulsar_deduplication: bool) -> Config {
+        let mut config: Config = envy::from_iter(Vec::<(String, String)>::new()).unwrap();
+        config.pulsar_producer_name = pulsar_producer_name.to_string();
+        config.pulsar_deduplication = pulsar_deduplication;
+        config
+    }
+
+    #[test]
+    fn test_cloudevents_topic_modes() {
+        // Arrange
+        let mut config = config(&default_pulsar_producer_name(), false);
+        config.cloudevents_topic_modes = Some(String::from(
+            "be.mediahaven.flow.archived=binary, be.mediahaven.export=structured",
+        ));
+        // Act
+        let topic_modes = config.cloudevents_topic_modes().unwrap();
+        // Assert
+        assert_eq!(
+            topic_modes["be.mediahaven.flow.archived"],
+            ContentMode::Binary
+        );
+        assert_eq!(topic_modes["be.mediahaven.export"], ContentMode::Structured);
+        config.cloudevents_topic_modes = Some(String::from("be.mediahaven.flow.archived=xml"));
+        assert!(config.cloudevents_topic_modes().is_err());
+    }
+
+    #[test]
+    fn test_try_new_invalid() {
+        let premis_event = r#"<premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+            <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+        </premis:event>"#;
+        assert!(Event::try_new(premis_event).is_err());
+        assert!(Event::try_new("<premis:event").is_err());
+    }
+
+    #[test]
+    fn test_try_new_invalid_event_type() {
+        for event_type in ["../../etc/cron.d/x", "FLOW/ARCHIVED", "FLOW ARCHIVED", ""] {
+            // Arrange
+            let mut value = json_event();
+            value["eventType"] = serde_json::json!(event_type);
+            // Act & Assert
+            assert!(Event::from_json(value).is_err(), "{}", event_type);
+        }
+        let premis_event = r#"<premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+            <premis:eventIdentifier>
+                <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+                <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+            </premis:eventIdentifier>
+            <premis:eventType>../FLOW.ARCHIVED</premis:eventType>
+            <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome>OK</premis:eventOutcome>
+            </premis:eventOutcomeInformation>
+            <premis:linkingAgentIdentifier>
+                <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+                <premis:linkingAgentIdentifierValue>703a53d2</premis:linkingAgentIdentifierValue>
+            </premis:linkingAgentIdentifier>
+            <premis:linkingObjectIdentifier>
+                <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+                <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+            </premis:linkingObjectIdentifier>
+        </premis:event>"#;
+        assert_eq!(
+            Event::try_new(premis_event).unwrap_err(),
+            "Invalid event type '../FLOW.ARCHIVED'."
+        );
+    }
+
+    #[test]
+    fn test_empty_as_none() {
+        let config: Config = envy::from_iter(vec![
+            (String::from("TLS_CERT"), String::new()),
+            (String::from("TLS_KEY"), String::from("/etc/tls/tls.key")),
+            (String::from("AUTH_SECRETS_FILE"), String::new()),
+        ])
+        .unwrap();
+        assert_eq!(config.tls_cert, None);
+        assert_eq!(config.tls_key.as_deref(), Some("/etc/tls/tls.key"));
+        assert_eq!(config.tls_client_ca, None);
+        assert_eq!(config.auth_secrets_file, None);
+    }
+
+    #[test]
+    fn test_rdf_topics() {
+        let mut config = config(&default_pulsar_producer_name(), false);
+        assert!(config.rdf_topics().is_empty());
+        assert_eq!(config.rdf_format, RdfFormat::JsonLd);
+        config.rdf_topics = Some(String::from(
+            "be.mediahaven.flow.archived, be.mediahaven.records.update,",
+        ));
+        assert_eq!(
+            config.rdf_topics(),
+            HashSet::from([
+                String::from("be.mediahaven.flow.archived"),
+                String::from("be.mediahaven.records.update"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_producer_name() {
+        // Arrange
+        let config = config("mh-events2pulsar-{hostname}-{pod_name}-{random}", false);
+        // Act
+        let name = config.render_producer_name("host", "pod", "1a2b3c4d");
+        // Assert
+        assert_eq!(name.unwrap(), "mh-events2pulsar-host-pod-1a2b3c4d");
+    }
+
+    #[test]
+    fn test_producer_name_random_with_deduplication() {
+        // Arrange
+        let config = config("mh-events2pulsar-{random}", true);
+        // Act
+        let name = config.render_producer_name("host", "pod", "1a2b3c4d");
+        // Assert
+        assert!(name.is_err());
+    }
+
+    #[test]
+    fn test_producer_name_default() {
+        // Arrange
+        let config = config(&default_pulsar_producer_name(), true);
+        // Act
+        let name = config.render_producer_name("mh-events2pulsar-7d9f-x2", "pod", "1a2b3c4d");
+        // Assert
+        assert_eq!(name.unwrap(), "mh-events2pulsar-mh-events2pulsar-7d9f-x2");
+    }
     #[test]
     fn test_trigger_export_request() {
         // Arrange
@@ -137,6 +1102,7 @@ mod tests {
         // Act
         let event = Event::new(body);
         // Assert
+        assert_eq!(event.identifier(), "111",);
         assert_eq!(event.event_type, "FLOW.ARCHIVED",);
         assert_eq!(
             event.event_timestamp,
@@ -285,4 +1251,262 @@ mod tests {
         assert_eq!(event.event_payload, body,);
         assert_eq!(event.to_xml(), body,);
     }
+
+    fn json_event() -> serde_json::Value {
+        serde_json::json!({
+            "eventIdentifier": {
+                "eventIdentifierType": "MEDIAHAVEN_EVENT",
+                "eventIdentifierValue": "111"
+            },
+            "eventType": "FLOW.ARCHIVED",
+            "eventDateTime": "2019-03-30T05:28:40Z",
+            "eventDetail": "Ingested <a1> & archived",
+            "eventOutcomeInformation": {"eventOutcome": "OK"},
+            "linkingAgentIdentifier": [{
+                "linkingAgentIdentifierType": "MEDIAHAVEN_USER",
+                "linkingAgentIdentifierValue": "703a53d2-dc66-4eb2-ab7f-73d5fd228852"
+            }],
+            "linkingObjectIdentifier": [{
+                "linkingObjectIdentifierType": "EXTERNAL_ID",
+                "linkingObjectIdentifierValue": "a1"
+            }]
+        })
+    }
+
+    #[test]
+    fn test_from_json() {
+        // Act
+        let event = Event::from_json(json_event()).unwrap();
+        // Assert
+        assert_eq!(event.identifier(), "111");
+        assert_eq!(event.event_type, "FLOW.ARCHIVED");
+        assert_eq!(event.subject(), "a1");
+        assert_eq!(
+            event.event_detail.as_deref(),
+            Some("Ingested <a1> & archived")
+        );
+        assert!(event
+            .to_xml()
+            .contains(r#"<premis:event xmlns:premis="info:lc/xmlns/premis-v2">"#));
+        assert!(event
+            .to_xml()
+            .contains("<premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>"));
+    }
+
+    #[test]
+    fn test_from_json_empty_field() {
+        // Arrange
+        let mut value = json_event();
+        value["eventType"] = serde_json::json!("");
+        // Act
+        let event = Event::from_json(value);
+        // Assert
+        assert_eq!(
+            event.unwrap_err(),
+            "The field 'eventType' can not be empty."
+        );
+    }
+
+    #[test]
+    fn test_from_json_missing_field() {
+        // Arrange
+        let mut value = json_event();
+        value.as_object_mut().unwrap().remove("eventIdentifier");
+        // Act & Assert
+        assert!(Event::from_json(value).is_err());
+    }
+
+    const PREMIS_EVENT_SCHEMA: &str = include_str!("../docs/premis-event.schema.json");
+
+    fn assert_valid(event: &serde_json::Value) {
+        let schema = serde_json::from_str(PREMIS_EVENT_SCHEMA).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let errors: Vec<String> = validator
+            .iter_errors(event)
+            .map(|e| e.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_to_json() {
+        // Arrange
+        let body = r##"<premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+            <premis:eventIdentifier>
+                <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+                <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+            </premis:eventIdentifier>
+            <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+            <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+            <premis:eventDetail>Archived</premis:eventDetail>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome>OK</premis:eventOutcome>
+                <premis:eventOutcomeDetail>
+                    <premis:eventOutcomeDetailNote>Archived on tape</premis:eventOutcomeDetailNote>
+                    <premis:eventOutcomeDetailExtension>
+                        <mh:fragmentId xmlns:mh="https://zeticon.mediahaven.com/metadata/20.3/mh/" type="main">f1</mh:fragmentId>
+                    </premis:eventOutcomeDetailExtension>
+                </premis:eventOutcomeDetail>
+            </premis:eventOutcomeInformation>
+            <premis:linkingAgentIdentifier>
+                <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+                <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+                <premis:linkingAgentRole>executor</premis:linkingAgentRole>
+            </premis:linkingAgentIdentifier>
+            <premis:linkingObjectIdentifier>
+                <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+                <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+            </premis:linkingObjectIdentifier>
+        </premis:event>"##;
+        // Act
+        let json = Event::new(body).to_json().unwrap();
+        // Assert
+        assert_valid(&json);
+        assert_eq!(json["namespace"], "info:lc/xmlns/premis-v2");
+        assert_eq!(json["eventIdentifier"]["eventIdentifierValue"], "111");
+        assert_eq!(json["eventDetail"], "Archived");
+        let outcome_detail = &json["eventOutcomeInformation"][0]["eventOutcomeDetail"][0];
+        assert_eq!(outcome_detail["eventOutcomeDetailNote"], "Archived on tape");
+        assert_eq!(
+            outcome_detail["eventOutcomeDetailExtension"][0]["extensions"][0],
+            serde_json::json!({
+                "name": "fragmentId",
+                "namespace": "https://zeticon.mediahaven.com/metadata/20.3/mh/",
+                "attributes": {"type": "main"},
+                "text": "f1",
+            })
+        );
+        assert_eq!(
+            json["linkingAgentIdentifier"][0]["linkingAgentRole"],
+            serde_json::json!(["executor"])
+        );
+        assert_eq!(
+            json["linkingObjectIdentifier"][0]["linkingObjectIdentifierValue"],
+            "a1"
+        );
+        // The JSON of an event is accepted as input as well.
+        assert_eq!(Event::from_json(json).unwrap().identifier(), "111");
+    }
+
+    #[test]
+    fn test_to_json_premis_v3() {
+        // Arrange
+        let body = r##"<premis:event xmlns:premis="http://www.loc.gov/premis/v3">
+            <premis:eventIdentifier>
+                <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+                <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+            </premis:eventIdentifier>
+            <premis:eventType>RECORDS.UPDATE</premis:eventType>
+            <premis:eventDateTime>2024-08-12T15:01:08.751Z</premis:eventDateTime>
+            <premis:eventDetailInformation>
+                <premis:eventDetail />
+                <premis:eventDetailExtension>
+                    <mhs:Difference xmlns:mhs="https://zeticon.mediahaven.com/metadata/24.1/mhs/">
+                        <mhs:MetadataFieldChange>
+                            <mhs:DottedKey>Dynamic.dc_types</mhs:DottedKey>
+                            <mhs:ValueBefore />
+                            <mhs:ValueAfter>
+                                <mh:multiselect xmlns:mh="https://zeticon.mediahaven.com/metadata/24.1/mh/">Drama</mh:multiselect>
+                            </mhs:ValueAfter>
+                        </mhs:MetadataFieldChange>
+                    </mhs:Difference>
+                </premis:eventDetailExtension>
+            </premis:eventDetailInformation>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome>OK</premis:eventOutcome>
+            </premis:eventOutcomeInformation>
+            <premis:linkingAgentIdentifier>
+                <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+                <premis:linkingAgentIdentifierValue>7c741085-71db-4ab0-8d3d-f350a3fc4b1b</premis:linkingAgentIdentifierValue>
+            </premis:linkingAgentIdentifier>
+            <premis:linkingObjectIdentifier>
+                <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+                <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+            </premis:linkingObjectIdentifier>
+        </premis:event>"##;
+        // Act
+        let json = Event::new(body).to_json().unwrap();
+        // Assert
+        assert_valid(&json);
+        let difference =
+            &json["eventDetailInformation"][0]["eventDetailExtension"][0]["extensions"][0];
+        assert_eq!(difference["name"], "Difference");
+        let change = &difference["children"][0];
+        assert_eq!(change["children"][0]["text"], "Dynamic.dc_types");
+        assert_eq!(
+            change["children"][2]["children"][0],
+            serde_json::json!({
+                "name": "multiselect",
+                "namespace": "https://zeticon.mediahaven.com/metadata/24.1/mh/",
+                "text": "Drama",
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_json_from_json() {
+        // Arrange
+        let event = Event::from_json(json_event()).unwrap();
+        // Act
+        let json = event.to_json().unwrap();
+        // Assert
+        assert_valid(&json);
+        assert_eq!(json["eventType"], "FLOW.ARCHIVED");
+        assert_eq!(json["eventDetail"], "Ingested <a1> & archived");
+    }
+
+    #[test]
+    fn test_to_json_round_trip() {
+        // Arrange
+        let body = r##"<premis:event xmlns:premis="http://www.loc.gov/premis/v3">
+            <premis:eventIdentifier>
+                <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+                <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+            </premis:eventIdentifier>
+            <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+            <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome authority="mediahaven">OK</premis:eventOutcome>
+                <premis:eventOutcomeDetail>
+                    Archived on tape
+                    <premis:eventOutcomeDetailExtension>
+                        <mh:fragmentId xmlns:mh="https://zeticon.mediahaven.com/metadata/20.3/mh/" type="main">f1</mh:fragmentId>
+                    </premis:eventOutcomeDetailExtension>
+                </premis:eventOutcomeDetail>
+            </premis:eventOutcomeInformation>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome>NOK</premis:eventOutcome>
+            </premis:eventOutcomeInformation>
+            <premis:linkingAgentIdentifier>
+                <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+                <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+            </premis:linkingAgentIdentifier>
+            <premis:linkingObjectIdentifier>
+                <premis:linkingObjectIdentifierType simpleLink="a1">EXTERNAL_ID</premis:linkingObjectIdentifierType>
+                <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+            </premis:linkingObjectIdentifier>
+        </premis:event>"##;
+        let json = Event::new(body).to_json().unwrap();
+        // Act
+        let event = Event::from_json(json.clone()).unwrap();
+        // Assert
+        assert_valid(&json);
+        assert_eq!(event.to_json().unwrap(), json);
+        assert_eq!(event.outcomes(), vec!["OK", "NOK"]);
+        assert!(event
+            .to_xml()
+            .contains(r#"<premis:event xmlns:premis="http://www.loc.gov/premis/v3">"#));
+        let outcome = &json["eventOutcomeInformation"][0]["eventOutcome"];
+        assert_eq!(
+            outcome,
+            &serde_json::json!({"@attributes": {"authority": "mediahaven"}, "#text": "OK"})
+        );
+        assert_eq!(json["eventOutcomeInformation"][1]["eventOutcome"], "NOK");
+        let outcome_detail = &json["eventOutcomeInformation"][0]["eventOutcomeDetail"][0];
+        assert_eq!(outcome_detail["#text"], "Archived on tape");
+        assert_eq!(
+            json["linkingObjectIdentifier"][0]["linkingObjectIdentifierType"],
+            serde_json::json!({"@attributes": {"simpleLink": "a1"}, "#text": "EXTERNAL_ID"})
+        );
+    }
 }
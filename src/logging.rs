@@ -0,0 +1,310 @@
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    Error, HttpMessage,
+};
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{warn, Record};
+use uuid::Uuid;
+
+use mh_events2pulsar::{Config, Event, LogFormat};
+
+type Fields = Vec<(&'static str, String)>;
+
+tokio::task_local! {
+    /// The fields added to every line logged by the current task.
+    static FIELDS: Fields;
+}
+
+/// The maximum length of a logged request body, 0 to not log them at all.
+static PAYLOAD_MAX_LENGTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Set up the logger.
+///
+/// The levels are set per module with `RUST_LOG`, e.g.
+/// `info,mh_events2pulsar::pulsar_client=debug`.
+pub fn init(config: &Config) {
+    PAYLOAD_MAX_LENGTH.store(config.log_payload_max_length, Ordering::Relaxed);
+    let log_format = config.log_format;
+    env_logger::Builder::from_default_env()
+        .format(move |buf, record| {
+            let fields = FIELDS.try_with(Clone::clone).unwrap_or_default();
+            writeln!(buf, "{}", format_record(&log_format, record, &fields))
+        })
+        .init();
+}
+
+/// Run `future` with `fields` added to every line it logs, next to the
+/// fields of the enclosing scope.
+pub async fn scope<F: Future>(fields: Fields, future: F) -> F::Output {
+    let mut all_fields = FIELDS.try_with(Clone::clone).unwrap_or_default();
+    all_fields.extend(fields);
+    FIELDS.scope(all_fields, future).await
+}
+
+/// Run `future` in the logging scope of the current task, for the tasks it
+/// spawns.
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let fields = FIELDS.try_with(Clone::clone).unwrap_or_default();
+    FIELDS.scope(fields, future)
+}
+
+/// The fields that identify a premis event in the logs.
+pub fn event_fields(premis_event: &Event) -> Fields {
+    vec![
+        ("event_identifier", premis_event.identifier().to_string()),
+        ("event_type", premis_event.event_type.clone()),
+        ("subject", premis_event.subject()),
+        ("topic", premis_event.topic()),
+    ]
+}
+
+/// The id of the request, set by `log_request`.
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+/// The id correlating the events of a request with the caller, set by
+/// `log_request`. It is the `X-Correlation-Id` or `X-Request-Id` the caller
+/// sent, or else the request id.
+#[derive(Clone)]
+pub struct CorrelationId(pub String);
+
+const CORRELATION_ID: HeaderName = HeaderName::from_static("x-correlation-id");
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+const BRIDGE_REQUEST_ID: HeaderName = HeaderName::from_static("x-bridge-request-id");
+
+/// An id sent by the caller, if it is usable: up to 128 printable ASCII
+/// characters.
+fn caller_id(headers: &HeaderMap, name: &HeaderName) -> Option<String> {
+    let value = headers.get(name)?.to_str().ok()?.trim();
+    if value.is_empty() || value.len() > 128 || !value.chars().all(|c| c.is_ascii_graphic()) {
+        warn!("Ignored the invalid {} header.", name);
+        return None;
+    }
+    Some(value.to_string())
+}
+
+/// The correlation id sent by the caller, if it is usable.
+fn correlation_id(headers: &HeaderMap) -> Option<String> {
+    [CORRELATION_ID, REQUEST_ID]
+        .iter()
+        .find_map(|name| caller_id(headers, name))
+}
+
+/// Middleware giving the requests to `/events` an id and a correlation id,
+/// which are added to every line logged while handling them. The
+/// request id is returned in the `X-Bridge-Request-Id` response header and
+/// the correlation id in `X-Correlation-Id`. The `X-Request-Id` of the
+/// caller is echoed, without one it is the request id.
+pub async fn log_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if !req.path().starts_with("/events") {
+        return next.call(req).await;
+    }
+    let request_id = Uuid::new_v4().to_simple().to_string();
+    let correlation_id = correlation_id(req.headers()).unwrap_or_else(|| request_id.clone());
+    let caller_request_id =
+        caller_id(req.headers(), &REQUEST_ID).unwrap_or_else(|| request_id.clone());
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    req.extensions_mut()
+        .insert(CorrelationId(correlation_id.clone()));
+    let request_id_value = HeaderValue::from_str(&request_id).expect("The request id is ASCII");
+    let caller_request_id_value =
+        HeaderValue::from_str(&caller_request_id).expect("The caller request id is ASCII");
+    let correlation_id_value =
+        HeaderValue::from_str(&correlation_id).expect("The correlation id is ASCII");
+    let fields = vec![
+        ("request_id", request_id),
+        ("correlation_id", correlation_id),
+    ];
+    let echo = |headers: &mut HeaderMap| {
+        headers.insert(CORRELATION_ID, correlation_id_value.clone());
+        headers.insert(REQUEST_ID, caller_request_id_value.clone());
+        headers.insert(BRIDGE_REQUEST_ID, request_id_value.clone());
+    };
+    match scope(fields, next.call(req)).await {
+        Ok(mut res) => {
+            echo(res.headers_mut());
+            Ok(res)
+        }
+        Err(e) => {
+            let mut response = e.error_response();
+            echo(response.headers_mut());
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// The request body as it is logged: cut off at the configured length, or
+/// `None` if bodies are not logged.
+pub fn payload(body: &str) -> Option<String> {
+    truncate(body, PAYLOAD_MAX_LENGTH.load(Ordering::Relaxed))
+}
+
+fn truncate(body: &str, max_length: usize) -> Option<String> {
+    if max_length == 0 {
+        return None;
+    }
+    if body.len() <= max_length {
+        return Some(body.to_string());
+    }
+    let mut end = max_length;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(format!("{}... ({} bytes)", &body[..end], body.len()))
+}
+
+struct Collect(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Collect {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+fn format_record(log_format: &LogFormat, record: &Record, fields: &Fields) -> String {
+    let mut pairs: Vec<(String, String)> = fields
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect();
+    let mut collect = Collect(Vec::new());
+    // Collecting into a Vec does not fail.
+    let _ = record.key_values().visit(&mut collect);
+    pairs.extend(collect.0);
+    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    match log_format {
+        LogFormat::Json => {
+            let mut line = serde_json::Map::new();
+            line.insert("timestamp".into(), timestamp.into());
+            line.insert("level".into(), record.level().as_str().into());
+            line.insert("target".into(), record.target().into());
+            line.insert("message".into(), record.args().to_string().into());
+            for (key, value) in pairs {
+                line.insert(key, value.into());
+            }
+            serde_json::Value::Object(line).to_string()
+        }
+        LogFormat::Text => {
+            let mut line = format!(
+                "[{} {:<5} {}] {}",
+                timestamp,
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            for (key, value) in pairs {
+                if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+                    line.push_str(&format!(" {}={:?}", key, value));
+                } else {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+            }
+            line
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_format_json() {
+        // Arrange
+        let fields = vec![("request_id", String::from("abc"))];
+        let key_values = [("outcome", "published")];
+        // Act
+        let line = format_record(
+            &LogFormat::Json,
+            &Record::builder()
+                .args(format_args!("Sent event."))
+                .level(Level::Info)
+                .target("mh_events2pulsar")
+                .key_values(&key_values)
+                .build(),
+            &fields,
+        );
+        // Assert
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "Sent event.");
+        assert_eq!(line["request_id"], "abc");
+        assert_eq!(line["outcome"], "published");
+    }
+
+    #[test]
+    fn test_format_text() {
+        // Arrange
+        let fields = vec![("subject", String::from("a b"))];
+        // Act
+        let line = format_record(
+            &LogFormat::Text,
+            &Record::builder()
+                .args(format_args!("Sent event."))
+                .level(Level::Info)
+                .target("mh_events2pulsar")
+                .build(),
+            &fields,
+        );
+        // Assert
+        assert!(line.ends_with(r#"INFO  mh_events2pulsar] Sent event. subject="a b""#));
+    }
+
+    #[test]
+    fn test_correlation_id() {
+        // Arrange
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID, HeaderValue::from_static("upstream-1"));
+        // Act
+        let request_id = correlation_id(&headers);
+        headers.insert(CORRELATION_ID, HeaderValue::from_static("webhook 2"));
+        let invalid = correlation_id(&headers);
+        headers.insert(CORRELATION_ID, HeaderValue::from_static("webhook-2"));
+        let preferred = correlation_id(&headers);
+        // Assert
+        assert_eq!(request_id.unwrap(), "upstream-1");
+        assert_eq!(invalid.unwrap(), "upstream-1");
+        assert_eq!(preferred.unwrap(), "webhook-2");
+        assert_eq!(correlation_id(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("<events/>", 0), None);
+        assert_eq!(truncate("<events/>", 100).unwrap(), "<events/>");
+        assert_eq!(truncate("café", 4).unwrap(), "caf... (5 bytes)");
+    }
+
+    #[actix_web::test]
+    async fn test_scope() {
+        // Act
+        let fields = scope(vec![("request_id", String::from("abc"))], async {
+            scope(vec![("topic", String::from("t"))], async {
+                FIELDS.with(Clone::clone)
+            })
+            .await
+        })
+        .await;
+        // Assert
+        assert_eq!(
+            fields,
+            vec![
+                ("request_id", String::from("abc")),
+                ("topic", String::from("t"))
+            ]
+        );
+    }
+}
@@ -1,93 +1,525 @@
+use std::fs;
 use std::io::BufWriter;
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix_web::{
+    dev::ServiceResponse,
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    http::{
+        header::{CONTENT_LENGTH, LOCATION},
+        KeepAlive, StatusCode,
+    },
+    middleware::{from_fn, ErrorHandlerResponse, ErrorHandlers},
     web::{self, Data},
-    App, HttpResponse, HttpServer, Responder,
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError,
 };
-use log::{debug, error, info};
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use opentelemetry::{
+    trace::{FutureExt, Span, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use pulsar::proto::CommandSendReceipt;
+use uuid::Uuid;
 use xmltree::Element;
 
+mod accept_queue;
+mod allowlist;
+mod audit;
+mod auth;
+mod body;
+mod cloud_event;
+mod dedup;
+mod event_result;
+mod freshness;
+mod health;
+mod in_flight;
+mod logging;
+mod metrics;
 mod pulsar_client;
-use crate::pulsar_client::PulsarClient;
+mod spool;
+mod tap;
+mod telemetry;
+mod tls;
+use crate::accept_queue::{request_status, AcceptQueue, RequestStatus};
+use crate::allowlist::{check_allowlist, Allowlist};
+use crate::audit::{AuditArchive, AuditRecord};
+use crate::auth::{authenticate, authenticate_admin, AdminAuthenticator, Authenticator};
+use crate::body::{check_headers, BodyFormat};
+use crate::cloud_event::is_uri_reference;
+use crate::dedup::{Claim, Dedup};
+use crate::event_result::{EventResult, Outcome};
+use crate::freshness::{report_freshness, Freshness};
+use crate::health::{readyz, startupz, Health};
+use crate::in_flight::InFlight;
+use crate::logging::{log_request, CorrelationId, RequestId};
+use crate::metrics::{metrics, METRICS};
+use crate::pulsar_client::{message_id, PulsarConnection, SendError};
+use crate::spool::{Backoff, Spool, Spooled};
+use crate::tap::{tap, TAP};
+use crate::telemetry::trace_request;
 use mh_events2pulsar::{Config, Event};
 
 async fn livez() -> impl Responder {
     HttpResponse::Ok()
 }
 
+/// Replace the generic response to an oversized request body by one that
+/// tells the limit.
+fn payload_too_large<B>(
+    res: ServiceResponse<B>,
+    max_body_size: usize,
+) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let (req, _) = res.into_parts();
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    let message = match content_length {
+        Some(content_length) => format!(
+            "The request body of {} bytes exceeds the limit of {} bytes.",
+            content_length, max_body_size
+        ),
+        None => format!(
+            "The request body exceeds the limit of {} bytes.",
+            max_body_size
+        ),
+    };
+    let res = ServiceResponse::new(req, HttpResponse::PayloadTooLarge().body(message));
+    Ok(ErrorHandlerResponse::Response(res.map_into_right_body()))
+}
+
 /// The event endpoint.
 ///
-/// Parse incoming premis events and send them to a pulsar topic defined by the event type.
+/// Parse incoming premis events, as XML or JSON, and send them to a pulsar topic defined by the event type.
 ///
 /// # Arguments
 ///
-/// * `req_body` - The request body of the post call.
-/// * `pulsar_client` - The shared Pulsar client state used to send messages to a topic.
-async fn events(req_body: String, pulsar_client: web::Data<Mutex<PulsarClient>>) -> impl Responder {
-    debug!("Incoming event: {:?}", req_body);
-    let xml_result = Element::parse(req_body.as_bytes());
-    match xml_result {
-        Ok(xml_tree) => {
-            // One ore more premis events are contained in an Events node.
-            for child in xml_tree.children {
-                if child.as_element().unwrap().name == "event" {
-                    // Write child element (= the premis event) to a String.
-                    let buf = Vec::new();
-                    let mut writer = BufWriter::new(buf);
-                    match child.as_element().unwrap().write(&mut writer) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error: {}", e);
-                            return HttpResponse::InternalServerError().body(e.to_string());
-                        }
-                    }
-                    match String::from_utf8(writer.into_inner().unwrap()) {
-                        Ok(premis_event_xml) => {
-                            // Create the Event struct
-                            let premis_event = Event::new(&premis_event_xml);
-                            // The topic part in: persistent://{tenant}/{namespace}/{topic}.
-                            let topic = format!(
-                                "be.mediahaven.{}",
-                                &premis_event.event_type.to_lowercase()
-                            );
-                            // Send message to Pulsar topic.
-                            let send_message_result = pulsar_client
-                                .lock()
-                                .unwrap()
-                                .send_message(&topic, &premis_event)
-                                .await;
-                            match send_message_result {
-                                Ok(_) => {
-                                    info!("Sent event on topic: '{}'.", &topic);
-                                }
-                                Err(e) => {
-                                    error!("Error: {}", e);
-                                    return HttpResponse::InternalServerError().body(e.to_string());
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Error: {}", e);
-                            return HttpResponse::InternalServerError().body(e.to_string());
-                        }
+/// * `req` - The request, for the remote address and the content type.
+/// * `req_body` - The request body of the post call, already decompressed.
+/// * `pulsar_connection` - The shared Pulsar client state used to send messages to a topic.
+/// * `in_flight` - The events that are being sent, reported on shutdown if they never got acknowledged.
+/// * `dedup` - The events that have already been published, to drop redeliveries.
+/// * `audit_archive` - Where the raw request is archived, if configured.
+/// * `accept_queue` - Where the events are queued in asynchronous mode, if configured.
+/// * `freshness` - When every event type was last received.
+#[allow(clippy::too_many_arguments)]
+async fn events(
+    req: HttpRequest,
+    req_body: web::Bytes,
+    pulsar_connection: web::Data<PulsarConnection>,
+    in_flight: web::Data<InFlight>,
+    dedup: web::Data<Dedup>,
+    audit_archive: Option<web::Data<AuditArchive>>,
+    accept_queue: Option<web::Data<AcceptQueue>>,
+    freshness: Option<web::Data<Freshness>>,
+) -> impl Responder {
+    let received_at = Utc::now();
+    let request_id = match req.extensions().get::<RequestId>() {
+        Some(RequestId(request_id)) => request_id.clone(),
+        None => Uuid::new_v4().to_simple().to_string(),
+    };
+    let correlation_id = match req.extensions().get::<CorrelationId>() {
+        Some(CorrelationId(correlation_id)) => correlation_id.clone(),
+        None => request_id.clone(),
+    };
+    let mut results = Vec::new();
+    METRICS.request_size.observe(req_body.len() as f64);
+    let decoded = check_headers(req.headers())
+        .and_then(|format| Ok((format, body::decode(req.headers(), &req_body)?)));
+    let (response, req_body) = match decoded {
+        Ok((format, req_body)) => {
+            if let Some(payload) = logging::payload(&req_body) {
+                debug!(payload:% = payload; "Incoming request body.");
+            }
+            let parse_timer = METRICS.parse_duration.start_timer();
+            let premis_events = {
+                let cx = Context::current_with_span(telemetry::tracer().start("parse events"));
+                let _guard = cx.clone().attach();
+                let premis_events = parse_events(format, &req_body);
+                match &premis_events {
+                    Ok(premis_events) => cx
+                        .span()
+                        .set_attribute(KeyValue::new("events", premis_events.len() as i64)),
+                    Err(e) => cx.span().set_status(Status::error(e.to_string())),
+                }
+                premis_events
+            };
+            parse_timer.observe_duration();
+            if let Ok(premis_events) = &premis_events {
+                for premis_event in premis_events {
+                    METRICS
+                        .events_received
+                        .with_label_values(&[METRICS
+                            .event_type_label(&premis_event.event_type)
+                            .as_str()])
+                        .inc();
+                    if let Some(freshness) = &freshness {
+                        freshness.record(
+                            &premis_event.event_type,
+                            premis_event.event_timestamp,
+                            received_at,
+                        );
                     }
                 }
             }
+            let response = match (premis_events, accept_queue) {
+                (Ok(premis_events), Some(accept_queue)) => accept_events(
+                    &request_id,
+                    &correlation_id,
+                    premis_events,
+                    pulsar_connection,
+                    dedup,
+                    accept_queue,
+                    &mut results,
+                ),
+                (Ok(premis_events), None) => {
+                    publish_events(
+                        premis_events,
+                        &correlation_id,
+                        &pulsar_connection,
+                        &in_flight,
+                        &dedup,
+                        &mut results,
+                    )
+                    .await
+                }
+                (Err(e), _) => e.error_response(),
+            };
+            (response, req_body)
         }
         Err(e) => {
             error!("Error: {}", e);
-            return HttpResponse::BadRequest().body(e.to_string());
+            (
+                e.error_response(),
+                String::from_utf8_lossy(&req_body).into_owned(),
+            )
+        }
+    };
+    for result in &results {
+        METRICS.observe_result(result);
+    }
+    if response.status().is_client_error() {
+        let reason = match response.status() {
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_body",
+            _ => "invalid_body",
+        };
+        METRICS.reject(response.status(), reason);
+    }
+    if let Some(audit_archive) = audit_archive {
+        audit_archive.write(&AuditRecord {
+            received_at,
+            remote_addr: req.peer_addr().map(|addr| addr.to_string()),
+            request_id: &request_id,
+            correlation_id: &correlation_id,
+            status: response.status().as_u16(),
+            body: &req_body,
+            results: &results,
+        });
+    }
+    response
+}
+
+/// Parse the premis events in the body.
+fn parse_events(format: BodyFormat, req_body: &str) -> Result<Vec<Event>, actix_web::Error> {
+    match format {
+        BodyFormat::Xml => parse_xml_events(req_body),
+        BodyFormat::Json => parse_json_events(req_body),
+    }
+}
+
+/// One ore more premis events are contained in an Events node.
+fn parse_xml_events(req_body: &str) -> Result<Vec<Event>, actix_web::Error> {
+    let xml_tree = Element::parse(req_body.as_bytes()).map_err(|e| {
+        error!("Error: {}", e);
+        ErrorBadRequest(e.to_string())
+    })?;
+    let mut premis_events = Vec::new();
+    for child in xml_tree.children {
+        // Comments and text between the events are skipped.
+        let Some(element) = child.as_element() else {
+            continue;
+        };
+        if element.name == "event" {
+            // Write child element (= the premis event) to a String.
+            let buf = Vec::new();
+            let mut writer = BufWriter::new(buf);
+            if let Err(e) = element.write(&mut writer) {
+                error!("Error: {}", e);
+                return Err(ErrorInternalServerError(e.to_string()));
+            }
+            match String::from_utf8(writer.into_inner().unwrap()) {
+                // Create the Event struct
+                Ok(premis_event_xml) => premis_events.push(
+                    parse_event(|| Event::try_new(&premis_event_xml)).map_err(|e| {
+                        error!("Error: {}", e);
+                        ErrorBadRequest(e)
+                    })?,
+                ),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    return Err(ErrorInternalServerError(e.to_string()));
+                }
+            }
+        }
+    }
+    Ok(premis_events)
+}
+
+/// Parse a single premis event in a span of its own.
+fn parse_event(parse: impl FnOnce() -> Result<Event, String>) -> Result<Event, String> {
+    let mut span = telemetry::tracer().start("parse event");
+    let premis_event = parse();
+    match &premis_event {
+        Ok(premis_event) => span.set_attributes([
+            KeyValue::new("mh.event.identifier", premis_event.identifier().to_string()),
+            KeyValue::new("mh.event.type", premis_event.event_type.clone()),
+        ]),
+        Err(e) => span.set_status(Status::error(e.clone())),
+    }
+    span.end();
+    premis_event
+}
+
+/// A single premis event as a JSON object, or several in an array.
+fn parse_json_events(req_body: &str) -> Result<Vec<Event>, actix_web::Error> {
+    let bad_request = |e: String| {
+        error!("Error: {}", e);
+        ErrorBadRequest(e)
+    };
+    let values = match serde_json::from_str(req_body).map_err(|e| bad_request(e.to_string()))? {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            parse_event(|| Event::from_json(value))
+                .map_err(|e| bad_request(format!("Invalid event at index {}: {}", index, e)))
+        })
+        .collect()
+}
+
+/// Queue the premis events and publish them in the background.
+///
+/// The response is `202 Accepted` with the status of the request, which can
+/// be followed at `/events/requests/{request_id}`.
+fn accept_events(
+    request_id: &str,
+    correlation_id: &str,
+    premis_events: Vec<Event>,
+    pulsar_connection: web::Data<PulsarConnection>,
+    dedup: web::Data<Dedup>,
+    accept_queue: web::Data<AcceptQueue>,
+    results: &mut Vec<EventResult>,
+) -> HttpResponse {
+    match accept_queue.enqueue(request_id, &premis_events) {
+        Ok(queued) => results.extend(queued),
+        Err(e) => {
+            error!("Could not queue the events: {}", e);
+            return HttpResponse::ServiceUnavailable()
+                .body(format!("Could not queue the events: {}", e));
+        }
+    }
+    info!(
+        "Accepted {} event(s) as request '{}'.",
+        premis_events.len(),
+        request_id
+    );
+    let pending = premis_events
+        .iter()
+        .enumerate()
+        .map(|(index, premis_event)| PendingEvent {
+            premis_event_xml: premis_event.to_xml(),
+            correlation_id: correlation_id.to_string(),
+            kept: Kept::Accepted {
+                request_id: request_id.to_string(),
+                index,
+            },
+        })
+        .collect();
+    actix_web::rt::spawn(logging::inherit(
+        async move {
+            let retry_backoff = accept_queue.retry_backoff();
+            publish_pending(
+                pending,
+                &pulsar_connection,
+                &dedup,
+                Some(&accept_queue),
+                retry_backoff,
+            )
+            .await;
+        }
+        // Part of the trace and the logging scope of the request.
+        .with_context(Context::current()),
+    ));
+    HttpResponse::Accepted()
+        .insert_header((LOCATION, format!("/events/requests/{}", request_id)))
+        .json(RequestStatus {
+            request_id: request_id.to_string(),
+            done: false,
+            events: results.clone(),
+        })
+}
+
+/// Publish the premis events, keeping track of what happened to each of
+/// them in `results`. The messages all get the `correlation_id` of the
+/// request.
+async fn publish_events(
+    premis_events: Vec<Event>,
+    correlation_id: &str,
+    pulsar_connection: &PulsarConnection,
+    in_flight: &InFlight,
+    dedup: &Dedup,
+    results: &mut Vec<EventResult>,
+) -> HttpResponse {
+    for premis_event in premis_events {
+        let fields = logging::event_fields(&premis_event);
+        let response = logging::scope(
+            fields,
+            publish_event(
+                premis_event,
+                correlation_id,
+                pulsar_connection,
+                in_flight,
+                dedup,
+                results,
+            ),
+        )
+        .await;
+        if let Some(response) = response {
+            return response;
         }
     }
     HttpResponse::Ok().finish()
 }
 
+/// Send the CloudEvent of a premis event and, for the RDF topics, its RDF.
+///
+/// An acknowledged CloudEvent is remembered on its own, so a redelivery of
+/// an event of which only the RDF failed does not publish the CloudEvent
+/// again. The receipt is the one of the CloudEvent, or of the RDF when only
+/// that was sent.
+async fn send_event(
+    topic: &str,
+    premis_event: &Event,
+    correlation_id: &str,
+    pulsar_connection: &PulsarConnection,
+    dedup: &Dedup,
+    dedup_key: &str,
+) -> Result<CommandSendReceipt, SendError> {
+    if !pulsar_connection.publishes_rdf(topic).await {
+        return pulsar_connection
+            .send_message(topic, premis_event, correlation_id)
+            .await;
+    }
+    let cloud_event_key = format!("{}:cloudevent", dedup_key);
+    let receipt = if dedup.contains(&cloud_event_key) {
+        None
+    } else {
+        let receipt = pulsar_connection
+            .send_message(topic, premis_event, correlation_id)
+            .await?;
+        dedup.remember(&cloud_event_key);
+        Some(receipt)
+    };
+    let rdf_receipt = pulsar_connection
+        .send_rdf(topic, premis_event, correlation_id)
+        .await?;
+    Ok(receipt.unwrap_or(rdf_receipt))
+}
+
+/// Publish a single premis event, the response is set when it failed.
+async fn publish_event(
+    premis_event: Event,
+    correlation_id: &str,
+    pulsar_connection: &PulsarConnection,
+    in_flight: &InFlight,
+    dedup: &Dedup,
+    results: &mut Vec<EventResult>,
+) -> Option<HttpResponse> {
+    let topic = premis_event.topic();
+    // MediaHaven redelivered an event that has already been published.
+    let dedup_key = dedup.key(&premis_event);
+    let mut result = EventResult {
+        identifier: premis_event.identifier().to_string(),
+        event_type: premis_event.event_type.clone(),
+        topic: topic.clone(),
+        outcome: Outcome::Published { message_id: None },
+    };
+    let reservation = match dedup.reserve(&dedup_key) {
+        Claim::New(reservation) => reservation,
+        Claim::Duplicate => {
+            result.outcome = Outcome::Duplicate;
+            info!(
+                outcome = result.outcome.label();
+                "Skipped duplicate event '{}' for topic: '{}'.",
+                premis_event.identifier(),
+                &topic
+            );
+            TAP.publish(&premis_event, correlation_id, &result);
+            results.push(result);
+            return None;
+        }
+        // MediaHaven retries, the event is a duplicate or published then.
+        Claim::InFlight => {
+            let reason = format!(
+                "The event '{}' is being published by another request.",
+                premis_event.identifier()
+            );
+            result.outcome = Outcome::Failed {
+                reason: reason.clone(),
+            };
+            warn!(outcome = result.outcome.label(); "{}", reason);
+            TAP.publish(&premis_event, correlation_id, &result);
+            results.push(result);
+            return Some(HttpResponse::ServiceUnavailable().body(reason));
+        }
+    };
+    // Send message to Pulsar topic and wait for the broker to acknowledge it.
+    let pending_id = in_flight.register(&topic, &premis_event.to_xml());
+    let send_message_result = send_event(
+        &topic,
+        &premis_event,
+        correlation_id,
+        pulsar_connection,
+        dedup,
+        &dedup_key,
+    )
+    .await;
+    in_flight.complete(pending_id);
+    let response = match send_message_result {
+        Ok(receipt) => {
+            reservation.remember();
+            result.outcome = Outcome::Published {
+                message_id: message_id(&receipt),
+            };
+            info!(outcome = result.outcome.label(); "Sent event on topic: '{}'.", &topic);
+            None
+        }
+        Err(e) => {
+            result.outcome = Outcome::Failed {
+                reason: e.to_string(),
+            };
+            error!(outcome = result.outcome.label(); "Error: {}", e);
+            let mut response = match e {
+                SendError::NotConnected => HttpResponse::ServiceUnavailable(),
+                SendError::Pulsar(_) => HttpResponse::InternalServerError(),
+            };
+            Some(response.body(e.to_string()))
+        }
+    };
+    TAP.publish(&premis_event, correlation_id, &result);
+    results.push(result);
+    response
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    //Initialize the logger
-    env_logger::init();
-
     // Get our configuration from the environment
     // The necessary environment variables can be found in the `.env` file
     let config = match envy::from_env::<Config>() {
@@ -95,42 +527,1026 @@ async fn main() -> std::io::Result<()> {
         Err(error) => panic!("{:#?}", error),
     };
 
-    // Intanstiate a Pulsar client to pass as a shared mutable state.
-    let pulsar_client = PulsarClient::new(&config).await.unwrap();
-    let client = Arc::new(Mutex::new(pulsar_client));
-    info!("Started the Pulsar client.");
+    //Initialize the logger
+    logging::init(&config);
+
+    if let Err(error) = config.producer_name() {
+        panic!("{}", error)
+    }
+    if let Err(error) = config.cloudevents_topic_modes() {
+        panic!("{}", error)
+    }
+    if !is_uri_reference(&config.cloudevents_source) {
+        panic!(
+            "The CloudEvents source '{}' is not a URI-reference.",
+            config.cloudevents_source
+        )
+    }
+
+    let tracer_provider = match telemetry::init(&config) {
+        Ok(tracer_provider) => tracer_provider,
+        Err(error) => panic!("Could not set up tracing: {}", error),
+    };
+
+    // Connect to Pulsar in the background so the HTTP server also starts
+    // when the broker is not reachable. The client is passed as a shared state.
+    let pulsar_connection = Arc::new(PulsarConnection::default());
+    actix_web::rt::spawn(pulsar_connection.clone().connect(config.clone()));
+    if config.pulsar_producer_idle_timeout > 0 {
+        actix_web::rt::spawn(
+            pulsar_connection
+                .clone()
+                .close_idle_producers(Duration::from_secs(config.pulsar_producer_idle_timeout)),
+        );
+    }
+    let in_flight = Arc::new(InFlight::default());
+    let dedup = match Dedup::new(
+        config.dedup_capacity,
+        config.dedup_ttl,
+        config.dedup_content_hash,
+        config.dedup_journal.as_deref(),
+    ) {
+        Ok(dedup) => Arc::new(dedup),
+        Err(error) => panic!("Could not open the deduplication journal: {}", error),
+    };
+    let server_pulsar_connection = pulsar_connection.clone();
+    let audit_archive = config.audit_dir.as_ref().map(|dir| {
+        match AuditArchive::new(
+            dir,
+            config.audit_max_file_size,
+            Duration::from_secs(config.audit_max_file_age),
+            Duration::from_secs(config.audit_retention_days * 24 * 60 * 60),
+        ) {
+            Ok(audit_archive) => Arc::new(audit_archive),
+            Err(error) => panic!("Could not open the audit archive at '{}': {}", dir, error),
+        }
+    });
+    if let Some(audit_archive) = &audit_archive {
+        audit_archive.clone().watch();
+    }
+    let authenticator = match Authenticator::new(&config) {
+        Ok(authenticator) => Arc::new(authenticator),
+        Err(error) => panic!("Could not set up the authentication: {}", error),
+    };
+    let admin_authenticator = AdminAuthenticator::new(&config).map(Arc::new);
+    if admin_authenticator.is_none() {
+        info!("No ADMIN_TOKEN configured, the /admin endpoints are disabled.");
+    }
+    let allowlist = match Allowlist::new(
+        config.allowed_ips.as_deref(),
+        config.trusted_proxies.as_deref(),
+        &config.trusted_proxy_header,
+    ) {
+        Ok(allowlist) => Arc::new(allowlist),
+        Err(error) => panic!("{}", error),
+    };
+    let tls_server_config = match tls::server_config(&config) {
+        Ok(tls_server_config) => tls_server_config,
+        Err(error) => panic!("Could not set up TLS: {}", error),
+    };
+    let freshness = match Freshness::new(config.freshness_thresholds.as_deref()) {
+        Ok(freshness) => Arc::new(freshness),
+        Err(error) => panic!("{}", error),
+    };
+    METRICS.add_event_types(config.metrics_event_types());
+    METRICS.add_event_types(freshness.event_types());
+    let health = Arc::new(Health::new(
+        Duration::from_secs(config.health_probe_interval),
+        config.spool_dir.clone(),
+        freshness.clone(),
+    ));
+    // Spooled events are retried as often as the broker is reconnected to.
+    let retry_backoff = Backoff::new(
+        Duration::from_millis(config.pulsar_reconnect_min_backoff),
+        Duration::from_millis(config.pulsar_reconnect_max_backoff),
+    );
+    let accept_queue = match (config.accept_async, &config.spool_dir) {
+        (false, _) => None,
+        (true, Some(dir)) => match Spool::new(dir) {
+            Ok(spool) => Some(Arc::new(AcceptQueue::new(
+                spool,
+                Duration::from_secs(config.request_status_ttl),
+                retry_backoff,
+            ))),
+            Err(error) => panic!("Could not open the spool at '{}': {}", dir, error),
+        },
+        (true, None) => panic!("ACCEPT_ASYNC requires SPOOL_DIR to queue the events."),
+    };
+    // Replay what was left in the spool by a previous run, before new
+    // requests are spooled.
+    if let Some(dir) = &config.spool_dir {
+        match Spool::new(dir).and_then(|spool| Ok((spool.spooled()?, spool))) {
+            Ok((spooled, spool)) if !spooled.is_empty() => {
+                actix_web::rt::spawn(replay_spool(
+                    spool,
+                    spooled,
+                    pulsar_connection.clone(),
+                    dedup.clone(),
+                    accept_queue.clone(),
+                    retry_backoff,
+                ));
+            }
+            Ok(_) => {}
+            Err(error) => panic!("Could not read the spool at '{}': {}", dir, error),
+        }
+    }
+    let server_in_flight = in_flight.clone();
+    let server_audit_archive = audit_archive.clone();
+    let server_dedup = dedup.clone();
+    // On SIGTERM/SIGINT the server stops accepting connections and waits for
+    // the in-flight requests to finish, up to the shutdown timeout.
     // Create the HTTP server.
-    info!("Starting the HTTP server on '127.0.0.1:8080'.");
-    HttpServer::new(move || {
-        App::new()
-            .app_data(Data::from(client.clone()))
-            .app_data(web::PayloadConfig::new(1000000)) // Set limit size to 1MB
-            .route("/livez", web::get().to(livez))
-            .route("/events", web::post().to(events))
+    let max_body_size = config.max_body_size;
+    let server = HttpServer::new(move || {
+        let app = App::new();
+        let app = match &server_audit_archive {
+            Some(audit_archive) => app.app_data(Data::from(audit_archive.clone())),
+            None => app,
+        };
+        let app = match &accept_queue {
+            Some(accept_queue) => app.app_data(Data::from(accept_queue.clone())),
+            None => app,
+        };
+        app.wrap(
+            ErrorHandlers::new().handler(StatusCode::PAYLOAD_TOO_LARGE, move |res| {
+                payload_too_large(res, max_body_size)
+            }),
+        )
+        // Wrapped last, so the span covers the error handlers as well.
+        .wrap(from_fn(trace_request))
+        .wrap(from_fn(log_request))
+        .app_data(Data::from(server_pulsar_connection.clone()))
+        .app_data(Data::from(server_in_flight.clone()))
+        .app_data(Data::from(server_dedup.clone()))
+        .app_data(Data::from(authenticator.clone()))
+        .app_data(Data::from(allowlist.clone()))
+        .app_data(Data::from(health.clone()))
+        .app_data(Data::from(freshness.clone()))
+        .app_data(web::PayloadConfig::new(max_body_size))
+        .route("/livez", web::get().to(livez))
+        .route("/readyz", web::get().to(readyz))
+        .route("/startupz", web::get().to(startupz))
+        .route("/metrics", web::get().to(metrics))
+        .service(
+            web::resource("/events")
+                .wrap(from_fn(authenticate))
+                // Wrapped last, so it runs before the authentication.
+                .wrap(from_fn(check_allowlist))
+                .route(web::post().to(events)),
+        )
+        .service(
+            web::resource("/events/requests/{request_id}")
+                .wrap(from_fn(authenticate))
+                .wrap(from_fn(check_allowlist))
+                .route(web::get().to(request_status)),
+        )
+        // The admin endpoints are only served with their own credentials.
+        .configure(|cfg| {
+            if let Some(admin_authenticator) = &admin_authenticator {
+                cfg.app_data(Data::from(admin_authenticator.clone()))
+                    .service(
+                        web::resource("/admin/freshness")
+                            .wrap(from_fn(authenticate_admin))
+                            .wrap(from_fn(check_allowlist))
+                            .route(web::get().to(report_freshness)),
+                    )
+                    .service(
+                        web::resource("/admin/tap")
+                            .wrap(from_fn(authenticate_admin))
+                            .wrap(from_fn(check_allowlist))
+                            .route(web::get().to(tap)),
+                    );
+            }
+        })
     })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    .shutdown_timeout(config.shutdown_timeout)
+    .keep_alive(match config.keep_alive {
+        0 => KeepAlive::Disabled,
+        keep_alive => KeepAlive::Timeout(Duration::from_secs(keep_alive)),
+    })
+    .client_request_timeout(Duration::from_millis(config.request_timeout));
+    let mut server = match config.workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    for address in config.bind_addresses.split(',').map(str::trim) {
+        server = match &tls_server_config {
+            Some(tls_server_config) => {
+                server.bind_rustls_0_23((address, config.port), tls_server_config.clone())?
+            }
+            None => server.bind((address, config.port))?,
+        };
+    }
+    for addr in server.addrs() {
+        info!("Starting the HTTP server on '{}'.", addr);
+    }
+    server.run().await?;
+
+    info!("Stopped the HTTP server, closing the Pulsar producers.");
+    for (topic, e) in pulsar_connection.close().await {
+        error!("Could not close the producer for topic '{}': {}", topic, e);
+    }
+    report_unsent(&in_flight, &config);
+    if let Some(audit_archive) = audit_archive {
+        audit_archive.close();
+    }
+    info!("Dropped {} duplicate event(s).", dedup.duplicates());
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            error!("Could not flush the spans: {}", e);
+        }
+    }
+    info!("Shut down.");
+    Ok(())
+}
+
+/// A spooled premis event that still has to be published.
+struct PendingEvent {
+    premis_event_xml: String,
+    correlation_id: String,
+    kept: Kept,
+}
+
+/// Where a pending event is kept until it is published.
+enum Kept {
+    /// The event at `index` of a request that was accepted.
+    Accepted { request_id: String, index: usize },
+    /// A file in the spool, removed once the event is published.
+    Spooled(PathBuf),
+}
+
+/// Publish the pending events, retrying those that fail with a backoff
+/// until all of them are published.
+///
+/// Every round tries each event that is left once, so an event that keeps
+/// failing does not hold up the others.
+async fn publish_pending(
+    mut pending: Vec<PendingEvent>,
+    pulsar_connection: &PulsarConnection,
+    dedup: &Dedup,
+    accept_queue: Option<&AcceptQueue>,
+    retry_backoff: Backoff,
+) {
+    // The events are already in the spool, they should not end up in there
+    // twice when the bridge shuts down while sending them.
+    let in_flight = InFlight::default();
+    let mut backoff = retry_backoff.min();
+    loop {
+        let mut failed = Vec::new();
+        for pending_event in pending {
+            let mut results = Vec::new();
+            publish_events(
+                vec![Event::new(&pending_event.premis_event_xml)],
+                &pending_event.correlation_id,
+                pulsar_connection,
+                &in_flight,
+                dedup,
+                &mut results,
+            )
+            .await;
+            let Some(result) = results.pop() else {
+                continue;
+            };
+            if matches!(result.outcome, Outcome::Failed { .. }) {
+                failed.push(pending_event);
+                continue;
+            }
+            METRICS.observe_result(&result);
+            match (&pending_event.kept, accept_queue) {
+                (Kept::Accepted { request_id, index }, Some(accept_queue)) => {
+                    accept_queue.finish(request_id, *index, result)
+                }
+                (Kept::Spooled(path), _) => {
+                    if let Err(e) = fs::remove_file(path) {
+                        error!(
+                            "Could not remove '{}' from the spool: {}",
+                            path.display(),
+                            e
+                        );
+                    }
+                }
+                (Kept::Accepted { .. }, None) => {}
+            }
+        }
+        if failed.is_empty() {
+            return;
+        }
+        warn!(
+            "{} spooled event(s) could not be published, retrying in {:?}.",
+            failed.len(),
+            backoff
+        );
+        actix_web::rt::time::sleep(backoff).await;
+        backoff = retry_backoff.next(backoff);
+        pending = failed;
+    }
+}
+
+/// Publish the events that a previous run left in the spool, i.e. the events
+/// it could not send and those of accepted requests that were not published.
+///
+/// An event that can not be parsed is set aside as `{file}.invalid`.
+async fn replay_spool(
+    spool: Spool,
+    spooled: Vec<Spooled>,
+    pulsar_connection: Arc<PulsarConnection>,
+    dedup: Arc<Dedup>,
+    accept_queue: Option<Arc<AcceptQueue>>,
+    retry_backoff: Backoff,
+) {
+    info!("Replaying {} spooled event(s).", spooled.len());
+    let mut pending = Vec::new();
+    for spooled in spooled {
+        let premis_event_xml = match fs::read_to_string(&spooled.path)
+            .map_err(|e| e.to_string())
+            .and_then(|xml| Event::try_new(&xml).map(|_| xml))
+        {
+            Ok(premis_event_xml) => premis_event_xml,
+            Err(e) => {
+                error!(
+                    "Could not replay the spooled event '{}': {}",
+                    spooled.path.display(),
+                    e
+                );
+                if let Err(e) = spool.set_aside(&spooled.path) {
+                    error!("Could not set '{}' aside: {}", spooled.path.display(), e);
+                }
+                continue;
+            }
+        };
+        let (correlation_id, kept) = match (spooled.accepted, &accept_queue) {
+            (Some((request_id, index)), Some(accept_queue)) => {
+                if let Err(e) = accept_queue.restore(&request_id) {
+                    warn!("Could not restore request '{}': {}", request_id, e);
+                }
+                (request_id.clone(), Kept::Accepted { request_id, index })
+            }
+            (accepted, _) => (
+                accepted.map_or_else(
+                    || Uuid::new_v4().to_simple().to_string(),
+                    |(request_id, _)| request_id,
+                ),
+                Kept::Spooled(spooled.path),
+            ),
+        };
+        pending.push(PendingEvent {
+            premis_event_xml,
+            correlation_id,
+            kept,
+        });
+    }
+    publish_pending(
+        pending,
+        &pulsar_connection,
+        &dedup,
+        accept_queue.as_deref(),
+        retry_backoff,
+    )
+    .await;
+    info!("Replayed the spool.");
+}
+
+/// Report the events that never got acknowledged by Pulsar and write them to
+/// the spool if one is configured.
+fn report_unsent(in_flight: &InFlight, config: &Config) {
+    let unsent = in_flight.drain();
+    if unsent.is_empty() {
+        return;
+    }
+    warn!("{} event(s) were not sent to Pulsar.", unsent.len());
+    let spool = match &config.spool_dir {
+        Some(dir) => match Spool::new(dir) {
+            Ok(spool) => Some(spool),
+            Err(e) => {
+                error!("Could not open the spool at '{}': {}", dir, e);
+                None
+            }
+        },
+        None => None,
+    };
+    for pending_event in unsent {
+        match &spool {
+            Some(spool) => match spool.write(&pending_event.topic, &pending_event.premis_event_xml)
+            {
+                Ok(path) => {
+                    METRICS
+                        .events_dead_lettered
+                        .with_label_values(&[METRICS.topic_label(&pending_event.topic)])
+                        .inc();
+                    warn!(
+                        "Wrote unsent event for topic '{}' to '{}'.",
+                        pending_event.topic,
+                        path.display()
+                    )
+                }
+                Err(e) => error!(
+                    "Could not spool unsent event for topic '{}': {}. Event: {}",
+                    pending_event.topic, e, pending_event.premis_event_xml
+                ),
+            },
+            None => error!(
+                "Unsent event for topic '{}': {}",
+                pending_event.topic, pending_event.premis_event_xml
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use actix_web::body::to_bytes;
+    use actix_web::http::header::{CONTENT_ENCODING, CONTENT_TYPE};
     use actix_web::{test, web, App};
+    use flate2::{write::GzEncoder, Compression};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use std::io::Write;
     use std::str::from_utf8;
 
     #[actix_web::test]
     async fn test_livez() {
         // Arrange
-        let mut app = test::init_service(App::new().route("/livez", web::get().to(livez))).await;
+        let app = test::init_service(App::new().route("/livez", web::get().to(livez))).await;
         // Act
         let req = test::TestRequest::with_uri("/livez").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_metrics() {
+        // Arrange
+        let app = test::init_service(App::new().route("/metrics", web::get().to(metrics))).await;
+        // Act
+        let req = test::TestRequest::with_uri("/metrics").to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert!(resp.status().is_success());
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert!(from_utf8(&body)
+            .unwrap()
+            .contains("# TYPE mh_events2pulsar_events_in_flight gauge"));
+    }
+
+    #[actix_web::test]
+    async fn test_readyz_not_connected() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(Health::new(
+                    Duration::from_secs(5),
+                    None,
+                    Arc::new(Freshness::new(None).unwrap()),
+                )))
+                .route("/readyz", web::get().to(readyz)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::with_uri("/readyz").to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn test_event_not_connected() {
+        // Arrange
+        let body = r##"<events>
+            <premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+            <premis:eventIdentifier>
+                <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+                <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+            </premis:eventIdentifier>
+            <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+            <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome>OK</premis:eventOutcome>
+            </premis:eventOutcomeInformation>
+            <premis:linkingAgentIdentifier>
+                <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+                <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+            </premis:linkingAgentIdentifier>
+            <premis:linkingObjectIdentifier>
+                <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+                <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+            </premis:linkingObjectIdentifier>
+            </premis:event>
+        </events>"##;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn test_event_duplicate() {
+        // Arrange
+        let body = r##"<events>
+            <premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+            <premis:eventIdentifier>
+                <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+                <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+            </premis:eventIdentifier>
+            <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+            <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome>OK</premis:eventOutcome>
+            </premis:eventOutcomeInformation>
+            <premis:linkingAgentIdentifier>
+                <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+                <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+            </premis:linkingAgentIdentifier>
+            <premis:linkingObjectIdentifier>
+                <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+                <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+            </premis:linkingObjectIdentifier>
+            </premis:event>
+        </events>"##;
+        let dedup = Dedup::new(10, 3600, false, None).unwrap();
+        dedup.remember("111");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(dedup))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
         // Assert
+        // Acknowledged without publishing, there is no Pulsar client.
         assert!(resp.status().is_success());
     }
 
+    #[actix_web::test]
+    async fn test_event_unauthenticated() {
+        // Arrange
+        let config: Config = envy::from_iter(vec![
+            (String::from("AUTH_MODE"), String::from("bearer")),
+            (String::from("AUTH_SECRETS"), String::from("secret")),
+        ])
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Authenticator::new(&config).unwrap()))
+                .service(
+                    web::resource("/events")
+                        .wrap(from_fn(authenticate))
+                        .route(web::post().to(events)),
+                ),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header(("Authorization", "Bearer wrong"))
+            .set_payload("<events/>")
+            .to_request();
+        let error = test::try_call_service(&app, req).await.err().unwrap();
+        // Assert
+        assert_eq!(error.as_response_error().status_code(), 401);
+        assert!(METRICS
+            .render()
+            .contains(r#"requests_rejected_total{reason="unauthenticated",status="401"}"#));
+    }
+
+    #[actix_web::test]
+    async fn test_event_hmac_gzip() {
+        // Arrange
+        let config: Config = envy::from_iter(vec![
+            (String::from("AUTH_MODE"), String::from("hmac")),
+            (String::from("AUTH_SECRETS"), String::from("secret")),
+            (String::from("AUTH_HMAC_MAX_SKEW"), String::from("0")),
+        ])
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Authenticator::new(&config).unwrap()))
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .service(
+                    web::resource("/events")
+                        .wrap(from_fn(authenticate))
+                        .route(web::post().to(events)),
+                ),
+        )
+        .await;
+        let body = gzip(b"<events></events>");
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(&body);
+        let signature = hex::encode(mac.finalize().into_bytes());
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_TYPE, "application/xml"))
+            .insert_header((CONTENT_ENCODING, "gzip"))
+            .insert_header(("X-Signature", signature))
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_event_denied() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(
+                    Allowlist::new(Some("192.0.2.0/24"), None, "X-Forwarded-For").unwrap(),
+                ))
+                .service(
+                    web::resource("/events")
+                        .wrap(from_fn(check_allowlist))
+                        .route(web::post().to(events)),
+                ),
+        )
+        .await;
+        let denied = METRICS.requests_denied.get();
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .peer_addr("198.51.100.1:1234".parse().unwrap())
+            .set_payload("<events/>")
+            .to_request();
+        let error = test::try_call_service(&app, req).await.err().unwrap();
+        // Assert
+        assert_eq!(error.as_response_error().status_code(), 403);
+        // No other test is denied by the allowlist.
+        assert_eq!(METRICS.requests_denied.get(), denied + 1);
+        assert!(!METRICS
+            .render()
+            .contains(r#"requests_rejected_total{reason="not_allowed",status="403"}"#));
+    }
+
+    #[actix_web::test]
+    async fn test_event_payload_too_large() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    ErrorHandlers::new().handler(StatusCode::PAYLOAD_TOO_LARGE, |res| {
+                        payload_too_large(res, 10)
+                    }),
+                )
+                .app_data(web::PayloadConfig::new(10))
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_LENGTH, "17"))
+            .set_payload("<events></events>")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Without a Content-Length, e.g. a chunked body.
+        let mut chunked_req = test::TestRequest::post()
+            .uri("/events")
+            .set_payload("<events></events>")
+            .to_request();
+        chunked_req.headers_mut().remove(CONTENT_LENGTH);
+        let chunked_resp = test::call_service(&app, chunked_req).await;
+        // Assert
+        assert_eq!(resp.status(), 413);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            from_utf8(&body).unwrap(),
+            "The request body of 17 bytes exceeds the limit of 10 bytes."
+        );
+        assert_eq!(chunked_resp.status(), 413);
+        let body = to_bytes(chunked_resp.into_body()).await.unwrap();
+        assert_eq!(
+            from_utf8(&body).unwrap(),
+            "The request body exceeds the limit of 10 bytes."
+        );
+    }
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_event_gzip() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_TYPE, "application/xml"))
+            .insert_header((CONTENT_ENCODING, "gzip"))
+            .set_payload(gzip(b"<events></events>"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_event_decompression_bomb() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(web::PayloadConfig::new(1000))
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        let body = format!("<events>{}</events>", " ".repeat(1_000_000));
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_ENCODING, "gzip"))
+            .set_payload(gzip(body.as_bytes()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 413);
+    }
+
+    #[actix_web::test]
+    async fn test_event_unsupported_media_type() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_TYPE, "text/plain"))
+            .set_payload("<events></events>")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 415);
+    }
+
+    #[actix_web::test]
+    async fn test_event_latin1() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_TYPE, "text/xml"))
+            .set_payload(
+                &b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><events><note>caf\xe9</note></events>"[..],
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_event_json_not_connected() {
+        // Arrange
+        let body = r##"[{
+            "eventIdentifier": {
+                "eventIdentifierType": "MEDIAHAVEN_EVENT",
+                "eventIdentifierValue": "111"
+            },
+            "eventType": "FLOW.ARCHIVED",
+            "eventDateTime": "2019-03-30T05:28:40Z",
+            "eventOutcomeInformation": {"eventOutcome": "OK"},
+            "linkingAgentIdentifier": [{
+                "linkingAgentIdentifierType": "MEDIAHAVEN_USER",
+                "linkingAgentIdentifierValue": "703a53d2-dc66-4eb2-ab7f-73d5fd228852"
+            }],
+            "linkingObjectIdentifier": [{
+                "linkingObjectIdentifierType": "EXTERNAL_ID",
+                "linkingObjectIdentifierValue": "a1"
+            }]
+        }]"##;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn test_event_json_invalid() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(r#"{"eventType": "FLOW.ARCHIVED"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 400);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert!(from_utf8(&body)
+            .unwrap()
+            .starts_with("Invalid event at index 0: missing field"));
+    }
+
+    #[actix_web::test]
+    async fn test_event_xml_invalid() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header((CONTENT_TYPE, "application/xml"))
+            .set_payload(
+                r#"<events><!-- one event --><premis:event xmlns:premis="info:lc/xmlns/premis-v2"><premis:eventType>FLOW.ARCHIVED</premis:eventType></premis:event></events>"#,
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_event_correlation_id() {
+        // Arrange
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(log_request))
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .route("/events", web::post().to(events)),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .insert_header(("X-Correlation-Id", "webhook-42"))
+            .insert_header(("X-Request-Id", "router-7"))
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(r#"{"eventType": "FLOW.ARCHIVED"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 400);
+        assert_eq!(
+            resp.headers().get("X-Correlation-Id").unwrap(),
+            "webhook-42"
+        );
+        assert_eq!(resp.headers().get("X-Request-Id").unwrap(), "router-7");
+        let bridge_request_id = resp.headers().get("X-Bridge-Request-Id").unwrap();
+        assert_ne!(bridge_request_id, "webhook-42");
+        assert_ne!(bridge_request_id, "router-7");
+    }
+
+    #[actix_web::test]
+    async fn test_event_accept_async() {
+        // Arrange
+        let body = r##"<events>
+            <premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+            <premis:eventIdentifier>
+                <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+                <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+            </premis:eventIdentifier>
+            <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+            <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+            <premis:eventOutcomeInformation>
+                <premis:eventOutcome>OK</premis:eventOutcome>
+            </premis:eventOutcomeInformation>
+            <premis:linkingAgentIdentifier>
+                <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+                <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+            </premis:linkingAgentIdentifier>
+            <premis:linkingObjectIdentifier>
+                <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+                <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+            </premis:linkingObjectIdentifier>
+            </premis:event>
+        </events>"##;
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let accept_queue = AcceptQueue::new(
+            Spool::new(dir.to_str().unwrap()).unwrap(),
+            Duration::from_secs(3600),
+            Backoff::new(Duration::from_secs(60), Duration::from_secs(60)),
+        );
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(log_request))
+                .app_data(Data::new(PulsarConnection::default()))
+                .app_data(Data::new(InFlight::default()))
+                .app_data(Data::new(Dedup::new(10, 3600, false, None).unwrap()))
+                .app_data(Data::new(accept_queue))
+                .route("/events", web::post().to(events))
+                .route(
+                    "/events/requests/{request_id}",
+                    web::get().to(request_status),
+                ),
+        )
+        .await;
+        // Act
+        let req = test::TestRequest::post()
+            .uri("/events")
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        // Assert
+        assert_eq!(resp.status(), 202);
+        let location = resp
+            .headers()
+            .get(LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let request_id = resp.headers().get("X-Bridge-Request-Id").unwrap().clone();
+        assert_eq!(resp.headers().get("X-Request-Id").unwrap(), request_id);
+        let accepted: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(accepted["request_id"], request_id.to_str().unwrap());
+        assert_eq!(accepted["events"][0]["outcome"], "queued");
+        // Without a broker the first attempt fails, the event is retried.
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        let req = test::TestRequest::get().uri(&location).to_request();
+        let status: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(status["request_id"], accepted["request_id"]);
+        assert_eq!(status["events"][0]["identifier"], "111");
+        assert_eq!(status["events"][0]["outcome"], "queued");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let req = test::TestRequest::get()
+            .uri("/events/requests/unknown")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
     #[actix_web::test]
     async fn test_event() {
         // Arrange
@@ -165,13 +1581,13 @@ mod tests {
         // TODO
 
         // Create a HTTP test service
-        let mut app = test::init_service(App::new().route("/events", web::post().to(events))).await;
+        let app = test::init_service(App::new().route("/events", web::post().to(events))).await;
         // Act
         let req = test::TestRequest::post()
             .uri("/events")
             .set_payload(body)
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         // Assert
         assert!(resp.status().is_success());
         let body = to_bytes(resp.into_body()).await.unwrap();
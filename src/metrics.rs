@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+use std::sync::{LazyLock, RwLock};
+
+use actix_web::{http::StatusCode, HttpResponse};
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::event_result::EventResult;
+use mh_events2pulsar::event_type_topic;
+
+/// The metrics of the bridge, exposed on `/metrics`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// The label of the event types, and their topics, that are not known. The
+/// event type is chosen by the client, every made up one would be a new
+/// series otherwise.
+const OTHER: &str = "other";
+
+pub struct Metrics {
+    registry: Registry,
+    pub events_received: IntCounterVec,
+    pub events_processed: IntCounterVec,
+    pub events_dead_lettered: IntCounterVec,
+    pub requests_rejected: IntCounterVec,
+    pub requests_denied: IntCounter,
+    pub parse_duration: Histogram,
+    pub broker_ack_duration: HistogramVec,
+    pub request_size: Histogram,
+    pub events_in_flight: IntGauge,
+    pub producers_active: IntGauge,
+    pub last_event_received: GaugeVec,
+    pub delivery_lag: HistogramVec,
+    // In upper case.
+    event_types: RwLock<HashSet<String>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace("mh_events2pulsar");
+        let histogram_opts =
+            |name: &str, help: &str| HistogramOpts::new(name, help).namespace("mh_events2pulsar");
+        let metrics = Metrics {
+            registry: Registry::new(),
+            events_received: IntCounterVec::new(
+                opts("events_received_total", "Premis events received."),
+                &["event_type"],
+            )
+            .unwrap(),
+            events_processed: IntCounterVec::new(
+                opts(
+                    "events_processed_total",
+                    "Premis events by what happened to them: published, duplicate, queued or failed.",
+                ),
+                &["event_type", "topic", "outcome"],
+            )
+            .unwrap(),
+            events_dead_lettered: IntCounterVec::new(
+                opts(
+                    "events_dead_lettered_total",
+                    "Premis events left in the spool because they could not be published.",
+                ),
+                &["topic"],
+            )
+            .unwrap(),
+            requests_rejected: IntCounterVec::new(
+                opts(
+                    "requests_rejected_total",
+                    "Requests that were rejected, by status and reason: unauthenticated, not_allowed, unsupported_body or invalid_body.",
+                ),
+                &["status", "reason"],
+            )
+            .unwrap(),
+            requests_denied: IntCounter::with_opts(opts(
+                "requests_denied_total",
+                "Requests denied because their address is not in the allowlist.",
+            ))
+            .unwrap(),
+            parse_duration: Histogram::with_opts(
+                histogram_opts("parse_duration_seconds", "Time to parse a request body.")
+                    .buckets(exponential_buckets(0.0001, 4.0, 8).unwrap()),
+            )
+            .unwrap(),
+            broker_ack_duration: HistogramVec::new(
+                histogram_opts(
+                    "broker_ack_duration_seconds",
+                    "Time from sending a message until the broker acknowledged it.",
+                )
+                .buckets(exponential_buckets(0.001, 2.0, 14).unwrap()),
+                &["topic"],
+            )
+            .unwrap(),
+            request_size: Histogram::with_opts(
+                histogram_opts("request_size_bytes", "Size of the request bodies.")
+                    .buckets(exponential_buckets(256.0, 4.0, 9).unwrap()),
+            )
+            .unwrap(),
+            events_in_flight: IntGauge::with_opts(opts(
+                "events_in_flight",
+                "Premis events waiting for the broker to acknowledge them.",
+            ))
+            .unwrap(),
+            producers_active: IntGauge::with_opts(opts(
+                "producers_active",
+                "Open Pulsar producers.",
+            ))
+            .unwrap(),
+            last_event_received: GaugeVec::new(
+                opts(
+                    "last_event_received_timestamp_seconds",
+                    "When the last premis event of a type was received.",
+                ),
+                &["event_type"],
+            )
+            .unwrap(),
+            delivery_lag: HistogramVec::new(
+                histogram_opts(
+                    "delivery_lag_seconds",
+                    "Time from the eventDateTime of a premis event until it was received.",
+                )
+                .buckets(exponential_buckets(0.5, 4.0, 10).unwrap()),
+                &["event_type"],
+            )
+            .unwrap(),
+            event_types: RwLock::new(HashSet::new()),
+        };
+        let registry = &metrics.registry;
+        registry
+            .register(Box::new(metrics.events_received.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.events_processed.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.events_dead_lettered.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.requests_rejected.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.requests_denied.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.parse_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.broker_ack_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.request_size.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.events_in_flight.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.producers_active.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.last_event_received.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.delivery_lag.clone()))
+            .unwrap();
+        metrics
+    }
+
+    /// Give the event types their own labels, see [`Metrics::event_type_label`].
+    pub fn add_event_types<'a>(&self, event_types: impl IntoIterator<Item = &'a str>) {
+        self.event_types
+            .write()
+            .unwrap()
+            .extend(event_types.into_iter().map(str::to_uppercase));
+    }
+
+    fn is_known(&self, event_type: &str) -> bool {
+        self.event_types
+            .read()
+            .unwrap()
+            .contains(&event_type.to_uppercase())
+    }
+
+    /// The label of an event type: the event type in upper case when it is
+    /// known, otherwise `other`.
+    pub fn event_type_label(&self, event_type: &str) -> String {
+        if self.is_known(event_type) {
+            event_type.to_uppercase()
+        } else {
+            String::from(OTHER)
+        }
+    }
+
+    /// The label of a topic: the topic of a known event type, or its RDF
+    /// topic, otherwise `other`.
+    pub fn topic_label<'a>(&self, topic: &'a str) -> &'a str {
+        let event_topic = topic.strip_suffix(".rdf").unwrap_or(topic);
+        let known = self
+            .event_types
+            .read()
+            .unwrap()
+            .iter()
+            .any(|event_type| event_type_topic(event_type) == event_topic);
+        if known {
+            topic
+        } else {
+            OTHER
+        }
+    }
+
+    /// Count what happened to a premis event.
+    pub fn observe_result(&self, result: &EventResult) {
+        self.events_processed
+            .with_label_values(&[
+                self.event_type_label(&result.event_type).as_str(),
+                self.topic_label(&result.topic),
+                result.outcome.label(),
+            ])
+            .inc();
+    }
+
+    /// Count a request that was rejected with a client error.
+    pub fn reject(&self, status: StatusCode, reason: &str) {
+        self.requests_rejected
+            .with_label_values(&[status.as_str(), reason])
+            .inc();
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Encoding to a Vec does not fail");
+        String::from_utf8(buffer).expect("The text format is UTF-8")
+    }
+}
+
+/// The metrics endpoint, for Prometheus to scrape.
+pub async fn metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(METRICS.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_result::Outcome;
+
+    #[test]
+    fn test_observe_result() {
+        // Arrange
+        METRICS.add_event_types(["metrics.test"]);
+        let result = EventResult {
+            identifier: String::from("111"),
+            event_type: String::from("METRICS.TEST"),
+            topic: String::from("be.mediahaven.metrics.test"),
+            outcome: Outcome::Published { message_id: None },
+        };
+        // Act
+        METRICS.observe_result(&result);
+        // Assert
+        assert!(METRICS.render().contains(
+            r#"mh_events2pulsar_events_processed_total{event_type="METRICS.TEST",outcome="published",topic="be.mediahaven.metrics.test"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_observe_result_other() {
+        // Arrange
+        let result = EventResult {
+            identifier: String::from("111"),
+            event_type: String::from("METRICS.MADE_UP"),
+            topic: String::from("be.mediahaven.metrics.made_up"),
+            outcome: Outcome::Failed {
+                reason: String::from("Broker down"),
+            },
+        };
+        // Act
+        METRICS.observe_result(&result);
+        // Assert
+        let metrics = METRICS.render();
+        assert!(!metrics.contains("METRICS.MADE_UP"));
+        assert!(metrics.contains(
+            r#"mh_events2pulsar_events_processed_total{event_type="other",outcome="failed",topic="other"}"#
+        ));
+    }
+
+    #[test]
+    fn test_labels() {
+        // Arrange
+        METRICS.add_event_types(["METRICS.LABELS"]);
+        // Act & Assert
+        assert_eq!(METRICS.event_type_label("metrics.Labels"), "METRICS.LABELS");
+        assert_eq!(METRICS.event_type_label("METRICS.UNKNOWN"), "other");
+        assert_eq!(
+            METRICS.topic_label("be.mediahaven.metrics.labels.rdf"),
+            "be.mediahaven.metrics.labels.rdf"
+        );
+        assert_eq!(
+            METRICS.topic_label("be.mediahaven.metrics.unknown"),
+            "other"
+        );
+    }
+}
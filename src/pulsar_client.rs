@@ -1,67 +1,288 @@
+use actix_web::rt::time::sleep;
 use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use opentelemetry::{
+    trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
 use pulsar::{
+    error::{ConnectionError, ProducerError},
     producer::{self, SendFuture},
-    ConnectionRetryOptions, Error as PulsarError, MultiTopicProducer, Pulsar, SerializeMessage,
+    proto::{command_get_topics_of_namespace::Mode, CommandSendReceipt},
+    ConnectionRetryOptions, Error as PulsarError, Producer, Pulsar, SerializeMessage,
     TokioExecutor,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::{json, to_vec};
-use std::collections::HashMap;
-use uuid::Uuid;
+use serde_json::to_vec;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
 
-use mh_events2pulsar::{Config, Event};
+use crate::cloud_event::{CloudEvent, SPEC_VERSION};
+use crate::metrics::METRICS;
+use crate::telemetry;
+use mh_events2pulsar::rdf::RdfFormat;
+use mh_events2pulsar::{CloudEventsData, Config, ContentMode, Event};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Message {
     pub data: String,
+    /// The `eventIdentifierValue`.
+    pub id: String,
+    /// The CloudEvents type, which is the topic.
+    pub event_type: String,
+    pub source: String,
     pub event_time: DateTime<Utc>,
     pub subject: String,
+    /// Shared by the messages of a request.
+    pub correlation_id: String,
+    /// The `eventOutcome`, comma separated when there are several.
+    pub outcome: String,
+    /// The W3C `traceparent` and `tracestate`, so consumers can continue the trace.
+    pub trace_context: HashMap<String, String>,
+    pub mode: ContentMode,
+    /// The premis event as JSON, for `data.event`.
+    pub event: Option<serde_json::Value>,
+    /// Whether `data.premis` is left out when there is a `data.event`.
+    pub event_only: bool,
 }
 
 impl SerializeMessage for Message {
     fn serialize_message(input: Self) -> Result<producer::Message, PulsarError> {
         let event_time = input.event_time;
-        let properties = HashMap::from([
-            (String::from("type"), String::from("structured")),
-            (String::from("source"), String::from("mh-events2pulsar")),
-            (String::from("subject"), input.subject),
-            (String::from("outcome"), String::from("success")),
-            (
-                String::from("correlation_id"),
-                Uuid::new_v4().to_simple().to_string(),
-            ),
-            (String::from("specversion"), String::from("1.0")),
+        // The trace context goes in the distributed tracing extension.
+        let mut extensions: BTreeMap<String, String> =
+            input.trace_context.clone().into_iter().collect();
+        extensions.insert(String::from("outcome"), input.outcome.clone());
+        extensions.insert(String::from("correlationid"), input.correlation_id.clone());
+        let mut cloud_event = CloudEvent {
+            specversion: String::from(SPEC_VERSION),
+            id: input.id,
+            source: input.source,
+            event_type: input.event_type,
+            time: event_time,
+            subject: input.subject,
+            datacontenttype: String::from("application/json"),
+            data: serde_json::Value::Null,
+            extensions,
+        };
+        let (payload, mut properties) = match input.mode {
+            ContentMode::Structured => {
+                // The properties stay as they were, consumers route on them.
+                let properties = HashMap::from([
+                    (String::from("type"), String::from("structured")),
+                    (String::from("source"), cloud_event.source.clone()),
+                    (String::from("subject"), cloud_event.subject.clone()),
+                    (String::from("outcome"), input.outcome),
+                    (String::from("id"), cloud_event.id.clone()),
+                    (String::from("correlation_id"), input.correlation_id),
+                    (String::from("specversion"), String::from(SPEC_VERSION)),
+                    (
+                        String::from("content_type"),
+                        String::from("application/cloudevents+json; charset=utf-8"),
+                    ),
+                ]);
+                let mut data = serde_json::Map::new();
+                if !(input.event_only && input.event.is_some()) {
+                    data.insert(String::from("premis"), input.data.into());
+                }
+                if let Some(event) = input.event {
+                    data.insert(String::from("event"), event);
+                }
+                cloud_event.data = serde_json::Value::Object(data);
+                (to_vec(&cloud_event).unwrap(), properties)
+            }
+            ContentMode::Binary => {
+                cloud_event.datacontenttype = String::from("application/xml");
+                let mut properties = cloud_event.binary_properties();
+                properties.insert(
+                    String::from("content_type"),
+                    String::from("application/xml; charset=utf-8"),
+                );
+                (input.data.into_bytes(), properties)
+            }
+        };
+        properties.extend(input.trace_context);
+
+        Ok(producer::Message {
+            payload,
+            event_time: Some(event_time.timestamp_millis() as u64),
+            properties,
+            ..Default::default()
+        })
+    }
+}
+
+/// What is sent to Pulsar for an event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Representation {
+    CloudEvent,
+    /// On the `{topic}.rdf` topics.
+    Rdf,
+}
+
+/// The topic of the RDF of the events of a topic.
+pub fn rdf_topic(topic: &str) -> String {
+    format!("{}.rdf", topic)
+}
+
+/// An event as RDF, for graph loaders.
+#[derive(Debug)]
+pub struct RdfMessage {
+    pub data: String,
+    /// The `eventIdentifierValue`.
+    pub id: String,
+    pub event_time: DateTime<Utc>,
+    pub correlation_id: String,
+    pub trace_context: HashMap<String, String>,
+    pub format: RdfFormat,
+}
+
+impl SerializeMessage for RdfMessage {
+    fn serialize_message(input: Self) -> Result<producer::Message, PulsarError> {
+        let mut properties = HashMap::from([
+            (String::from("id"), input.id),
+            (String::from("correlation_id"), input.correlation_id),
             (
                 String::from("content_type"),
-                String::from("application/cloudevents+json; charset=utf-8"),
+                format!("{}; charset=utf-8", input.format.content_type()),
             ),
         ]);
-        let payload = json!({
-            "datacontenttype": "application/json",
-            "data": {
-                "premis": input.data,
-            },
-            "type": &properties["type"],
-            "source": &properties["source"],
-            "subject": &properties["subject"],
-            "outcome": &properties["outcome"],
-            "correlation_id": &properties["correlation_id"],
-            "specversion": &properties["specversion"],
-            "content_type": &properties["content_type"],
-        });
-
+        properties.extend(input.trace_context);
         Ok(producer::Message {
-            payload: to_vec(&payload).unwrap(),
-            event_time: Some(event_time.timestamp_millis() as u64),
+            payload: input.data.into_bytes(),
+            event_time: Some(input.event_time.timestamp_millis() as u64),
             properties,
             ..Default::default()
         })
     }
 }
 
+/// The id of the message the broker stored, formatted like Pulsar does:
+/// `{ledger_id}:{entry_id}:{partition}`.
+pub fn message_id(receipt: &CommandSendReceipt) -> Option<String> {
+    receipt.message_id.as_ref().map(|message_id| {
+        format!(
+            "{}:{}:{}",
+            message_id.ledger_id,
+            message_id.entry_id,
+            message_id.partition.unwrap_or(-1)
+        )
+    })
+}
+
+/// The producer of a single topic.
+struct TopicProducer<P> {
+    producer: P,
+    last_used: Instant,
+}
+
+/// The producers of a client by topic, of which at most `max` are kept.
+struct Producers<P> {
+    producers: HashMap<String, TopicProducer<P>>,
+    max: usize,
+}
+
+impl<P> Producers<P> {
+    fn new(max: usize) -> Self {
+        Producers {
+            producers: HashMap::new(),
+            max,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.producers.len()
+    }
+
+    fn contains(&self, topic: &str) -> bool {
+        self.producers.contains_key(topic)
+    }
+
+    /// The producer of a topic, which is marked as used now.
+    fn get_mut(&mut self, topic: &str) -> Option<&mut P> {
+        let topic_producer = self.producers.get_mut(topic)?;
+        topic_producer.last_used = Instant::now();
+        Some(&mut topic_producer.producer)
+    }
+
+    /// Add the producer of a topic.
+    ///
+    /// Returns the producer that has to be closed: the least recently used
+    /// one when the maximum is reached, or `producer` itself when the topic
+    /// got one meanwhile.
+    fn insert(&mut self, topic: &str, producer: P) -> Option<(String, P)> {
+        if self.contains(topic) {
+            return Some((topic.to_string(), producer));
+        }
+        let mut evicted = None;
+        if self.producers.len() >= self.max {
+            let least_recently_used = self
+                .producers
+                .iter()
+                .min_by_key(|(_, topic_producer)| topic_producer.last_used)
+                .map(|(topic, _)| topic.clone());
+            if let Some(least_recently_used) = least_recently_used {
+                evicted = self
+                    .remove(&least_recently_used)
+                    .map(|producer| (least_recently_used, producer));
+            }
+        }
+        self.producers.insert(
+            topic.to_string(),
+            TopicProducer {
+                producer,
+                last_used: Instant::now(),
+            },
+        );
+        evicted
+    }
+
+    fn remove(&mut self, topic: &str) -> Option<P> {
+        self.producers
+            .remove(topic)
+            .map(|topic_producer| topic_producer.producer)
+    }
+
+    /// Take out the producers that have not been used for `idle_timeout`.
+    fn take_idle(&mut self, idle_timeout: Duration) -> Vec<(String, P)> {
+        let idle_topics: Vec<String> = self
+            .producers
+            .iter()
+            .filter(|(_, topic_producer)| topic_producer.last_used.elapsed() >= idle_timeout)
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        idle_topics
+            .into_iter()
+            .filter_map(|topic| self.remove(&topic).map(|producer| (topic, producer)))
+            .collect()
+    }
+
+    /// Take out all the producers.
+    fn take_all(&mut self) -> Vec<(String, P)> {
+        self.producers
+            .drain()
+            .map(|(topic, topic_producer)| (topic, topic_producer.producer))
+            .collect()
+    }
+}
+
 pub struct PulsarClient {
-    pub producer: MultiTopicProducer<TokioExecutor>,
+    pulsar: Pulsar<TokioExecutor>,
+    producers: Producers<Producer<TokioExecutor>>,
+    producer_name: String,
     pub namespace: String,
+    /// The CloudEvents source of the messages.
+    source: String,
+    content_mode: ContentMode,
+    topic_content_modes: HashMap<String, ContentMode>,
+    cloudevents_data: CloudEventsData,
+    rdf_topics: HashSet<String>,
+    rdf_format: RdfFormat,
 }
 
 impl PulsarClient {
@@ -73,30 +294,708 @@ impl PulsarClient {
             .build()
             .await?;
         let namespace = config.pulsar_namespace.clone();
-        let producer = pulsar
-            .producer()
-            .with_name("mh-events2pulsar")
-            .build_multi_topic();
+        let producer_name = config.producer_name().map_err(PulsarError::Custom)?;
+        info!("Using producer name '{}'.", producer_name);
         Ok(PulsarClient {
-            producer,
+            pulsar,
+            producers: Producers::new(config.pulsar_max_producers),
+            producer_name,
             namespace,
+            source: config.cloudevents_source.clone(),
+            content_mode: config.cloudevents_mode,
+            topic_content_modes: config
+                .cloudevents_topic_modes()
+                .map_err(PulsarError::Custom)?,
+            cloudevents_data: config.cloudevents_data,
+            rdf_topics: config.rdf_topics(),
+            rdf_format: config.rdf_format,
         })
     }
 
+    /// Whether the client has a producer for a topic.
+    pub fn has_producer(&self, topic: &str) -> bool {
+        self.producers.contains(topic)
+    }
+
+    /// Create the producer of a topic, to be added with
+    /// [`PulsarClient::add_producer`].
+    ///
+    /// The future does not borrow the client, so it can be awaited without
+    /// holding up the sends to the other topics.
+    fn create_producer(
+        &self,
+        topic: &str,
+    ) -> impl Future<Output = Result<Producer<TokioExecutor>, PulsarError>> + 'static {
+        let pulsar = self.pulsar.clone();
+        let topic = format!("persistent://public/{}/{}", self.namespace, topic);
+        let producer_name = self.producer_name.clone();
+        async move {
+            pulsar
+                .producer()
+                .with_topic(topic)
+                .with_name(producer_name)
+                .build()
+                .await
+        }
+    }
+
+    /// Add the producer of a topic.
+    ///
+    /// When the maximum number of producers is reached, the least recently
+    /// used one is taken out and returned to be closed.
+    fn add_producer(
+        &mut self,
+        topic: &str,
+        producer: Producer<TokioExecutor>,
+    ) -> Option<(String, Producer<TokioExecutor>)> {
+        let evicted = self.producers.insert(topic, producer);
+        match &evicted {
+            // Another request added one for the topic meanwhile.
+            Some((evicted_topic, _)) if evicted_topic == topic => {}
+            Some((evicted_topic, _)) => info!(
+                "Reached {} producers, closing the one of topic '{}'.",
+                self.producers.max, evicted_topic
+            ),
+            None => METRICS.producers_active.inc(),
+        }
+        evicted
+    }
+
+    /// Whether the events of a topic are also published as RDF.
+    pub fn publishes_rdf(&self, topic: &str) -> bool {
+        self.rdf_topics.contains(topic)
+    }
+
     pub async fn send_message(
         &mut self,
         topic: &str,
         event: &Event,
+        correlation_id: &str,
+        representation: Representation,
     ) -> Result<SendFuture, pulsar::Error> {
-        self.producer
-            .send(
-                format!("persistent://public/{}/{}", self.namespace, topic),
-                Message {
-                    data: event.to_xml(),
+        match representation {
+            Representation::CloudEvent => {
+                let message = self.message(topic, event, correlation_id);
+                self.send(topic, message).await
+            }
+            Representation::Rdf => {
+                let message = RdfMessage {
+                    data: event.to_rdf(self.rdf_format),
+                    id: event.identifier().to_string(),
                     event_time: event.event_timestamp,
-                    subject: event.subject(),
-                },
+                    correlation_id: correlation_id.to_string(),
+                    trace_context: telemetry::inject(&Context::current()),
+                    format: self.rdf_format,
+                };
+                self.send(topic, message).await
+            }
+        }
+    }
+
+    fn message(&self, topic: &str, event: &Event, correlation_id: &str) -> Message {
+        let mode = *self
+            .topic_content_modes
+            .get(topic)
+            .unwrap_or(&self.content_mode);
+        // The binary mode only carries the XML.
+        let json = match (mode, self.cloudevents_data) {
+            (ContentMode::Binary, _) | (_, CloudEventsData::Premis) => None,
+            _ => event
+                .to_json()
+                .inspect_err(|e| {
+                    warn!(
+                        "Could not render event {} as JSON, publishing the XML only: {}",
+                        event.identifier(),
+                        e
+                    )
+                })
+                .ok(),
+        };
+        Message {
+            data: event.to_xml(),
+            id: event.identifier().to_string(),
+            event_type: topic.to_string(),
+            source: self.source.clone(),
+            event_time: event.event_timestamp,
+            subject: event.subject(),
+            correlation_id: correlation_id.to_string(),
+            outcome: event.outcomes().join(","),
+            trace_context: telemetry::inject(&Context::current()),
+            mode,
+            event: json,
+            event_only: self.cloudevents_data == CloudEventsData::Event,
+        }
+    }
+
+    async fn send<T: SerializeMessage + Sized>(
+        &mut self,
+        topic: &str,
+        message: T,
+    ) -> Result<SendFuture, pulsar::Error> {
+        let producer = self.producers.get_mut(topic).ok_or_else(|| {
+            PulsarError::Custom(format!("There is no producer for topic '{}'.", topic))
+        })?;
+        // The receipt is awaited by the caller, after releasing the client.
+        let send_result = producer.send_non_blocking(message).await;
+        if let Err(e) = &send_result {
+            if is_fatal(e) {
+                self.discard_producer(topic);
+            }
+        }
+        send_result
+    }
+
+    /// Forget the producer of a topic without closing it, because it is
+    /// broken. It is recreated on the next send.
+    pub fn discard_producer(&mut self, topic: &str) {
+        if self.producers.remove(topic).is_some() {
+            METRICS.producers_active.dec();
+            warn!("Discarded the broken producer of topic '{}'.", topic);
+        }
+    }
+
+    /// Take out the producers that have not been used for `idle_timeout`, to
+    /// be closed.
+    pub fn take_idle_producers(
+        &mut self,
+        idle_timeout: Duration,
+    ) -> Vec<(String, Producer<TokioExecutor>)> {
+        let idle_producers = self.producers.take_idle(idle_timeout);
+        METRICS.producers_active.sub(idle_producers.len() as i64);
+        idle_producers
+    }
+
+    /// Close the producer of every topic that has been sent to.
+    ///
+    /// Returns the topics of which the producer could not be closed cleanly.
+    pub async fn close(&mut self) -> Vec<(String, PulsarError)> {
+        let mut errors = Vec::new();
+        let producers = self.producers.take_all();
+        METRICS.producers_active.sub(producers.len() as i64);
+        for (topic, mut producer) in producers {
+            if let Err(e) = producer.close().await {
+                errors.push((topic, e));
+            }
+        }
+        errors
+    }
+}
+
+/// Whether the connection to the broker itself is lost, so the whole client
+/// has to be rebuilt instead of a single producer.
+fn is_connection_lost(error: &PulsarError) -> bool {
+    matches!(
+        error,
+        PulsarError::Executor
+            | PulsarError::Connection(
+                ConnectionError::Io(_) | ConnectionError::Disconnected | ConnectionError::Shutdown
             )
+    )
+}
+
+/// Whether the producer can not recover from this error by itself and has to
+/// be rebuilt.
+fn is_fatal(error: &PulsarError) -> bool {
+    matches!(
+        error,
+        PulsarError::Connection(_)
+            | PulsarError::Executor
+            | PulsarError::Producer(ProducerError::Connection(_))
+            | PulsarError::Producer(ProducerError::Fenced)
+    )
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    /// There is no Pulsar client (yet).
+    NotConnected,
+    Pulsar(PulsarError),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::NotConnected => write!(f, "Not connected to Pulsar"),
+            SendError::Pulsar(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The Pulsar client shared by the request handlers.
+///
+/// The client is established in the background by [`PulsarConnection::connect`]
+/// and rebuilt whenever it breaks, so the HTTP server can run without Pulsar.
+#[derive(Default)]
+pub struct PulsarConnection {
+    // The client together with its generation, so a send that fails on an
+    // old client does not tear down its replacement.
+    client: Mutex<Option<(u64, PulsarClient)>>,
+    generation: AtomicU64,
+    broken: Notify,
+}
+
+impl PulsarConnection {
+    /// Keep a Pulsar client established, retrying with an exponential backoff.
+    ///
+    /// This never returns and is meant to be spawned.
+    pub async fn connect(self: Arc<Self>, config: Config) {
+        let min_backoff = Duration::from_millis(config.pulsar_reconnect_min_backoff);
+        let max_backoff = Duration::from_millis(config.pulsar_reconnect_max_backoff);
+        let mut backoff = min_backoff;
+        loop {
+            if self.client.lock().await.is_none() {
+                match PulsarClient::new(&config).await {
+                    Ok(pulsar_client) => {
+                        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+                        *self.client.lock().await = Some((generation, pulsar_client));
+                        backoff = min_backoff;
+                        info!("Started the Pulsar client.");
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Could not connect to Pulsar: {}. Retrying in {:?}.",
+                            e, backoff
+                        );
+                        sleep(backoff).await;
+                        backoff = (backoff * 2).min(max_backoff);
+                        continue;
+                    }
+                }
+            }
+            self.broken.notified().await;
+        }
+    }
+
+    /// Check that there is a client and that the broker answers it, by
+    /// listing the topics of the namespace.
+    ///
+    /// The round trip is made on a handle of the client, so the sends are
+    /// not held up meanwhile.
+    pub async fn check_connection(&self) -> Result<(), SendError> {
+        let (pulsar, namespace) = match self.client.lock().await.as_ref() {
+            Some((_, pulsar_client)) => (
+                pulsar_client.pulsar.clone(),
+                format!("public/{}", pulsar_client.namespace),
+            ),
+            None => return Err(SendError::NotConnected),
+        };
+        pulsar
+            .get_topics_of_namespace(namespace, Mode::Persistent)
             .await
+            .map(|_| ())
+            .map_err(SendError::Pulsar)
+    }
+
+    /// Send an event to a topic and wait for the broker to acknowledge it.
+    pub async fn send_message(
+        &self,
+        topic: &str,
+        event: &Event,
+        correlation_id: &str,
+    ) -> Result<CommandSendReceipt, SendError> {
+        self.send(topic, event, correlation_id, Representation::CloudEvent)
+            .await
+    }
+
+    /// Send the RDF of an event of a topic to `{topic}.rdf` and wait for the
+    /// broker to acknowledge it.
+    pub async fn send_rdf(
+        &self,
+        topic: &str,
+        event: &Event,
+        correlation_id: &str,
+    ) -> Result<CommandSendReceipt, SendError> {
+        self.send(
+            &rdf_topic(topic),
+            event,
+            correlation_id,
+            Representation::Rdf,
+        )
+        .await
+    }
+
+    /// Whether the events of a topic are also published as RDF.
+    pub async fn publishes_rdf(&self, topic: &str) -> bool {
+        self.client
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|(_, pulsar_client)| pulsar_client.publishes_rdf(topic))
+    }
+
+    async fn send(
+        &self,
+        topic: &str,
+        event: &Event,
+        correlation_id: &str,
+        representation: Representation,
+    ) -> Result<CommandSendReceipt, SendError> {
+        let tracer = telemetry::tracer();
+        let span = tracer
+            .span_builder(format!("send {}", topic))
+            .with_kind(SpanKind::Producer)
+            .with_attributes([
+                KeyValue::new("messaging.system", "pulsar"),
+                KeyValue::new("messaging.destination.name", topic.to_string()),
+                KeyValue::new("mh.event.identifier", event.identifier().to_string()),
+            ])
+            .start(&tracer);
+        let cx = Context::current_with_span(span);
+        let receipt = self
+            .send_and_wait(topic, event, correlation_id, representation)
+            .with_context(cx.clone())
+            .await;
+        let span = cx.span();
+        match &receipt {
+            Ok(receipt) => {
+                if let Some(message_id) = message_id(receipt) {
+                    span.set_attribute(KeyValue::new("messaging.message.id", message_id));
+                }
+            }
+            Err(e) => span.set_status(Status::error(e.to_string())),
+        }
+        span.end();
+        receipt
+    }
+
+    async fn send_and_wait(
+        &self,
+        topic: &str,
+        event: &Event,
+        correlation_id: &str,
+        representation: Representation,
+    ) -> Result<CommandSendReceipt, SendError> {
+        let created = self.create_producer(topic).await?;
+        let sent_at;
+        let (generation, send_message_result, evicted) = {
+            let mut client = self.client.lock().await;
+            let (generation, pulsar_client) = match client.as_mut() {
+                Some((generation, pulsar_client)) => (*generation, pulsar_client),
+                None => return Err(SendError::NotConnected),
+            };
+            let evicted = match created {
+                Some((created_generation, producer)) if created_generation == generation => {
+                    pulsar_client.add_producer(topic, producer)
+                }
+                // Created by a client that has been replaced meanwhile.
+                Some((_, producer)) => Some((topic.to_string(), producer)),
+                None => None,
+            };
+            sent_at = Instant::now();
+            let send_message_result = pulsar_client
+                .send_message(topic, event, correlation_id, representation)
+                .await;
+            (generation, send_message_result, evicted)
+        };
+        if let Some((evicted_topic, mut producer)) = evicted {
+            if let Err(e) = producer.close().await {
+                warn!(
+                    "Could not cleanly close the producer of topic '{}': {}",
+                    evicted_topic, e
+                );
+            }
+        }
+        let receipt = match send_message_result {
+            Ok(send_future) => send_future.await,
+            Err(e) => return Err(SendError::Pulsar(e)),
+        };
+        if receipt.is_ok() {
+            METRICS
+                .broker_ack_duration
+                .with_label_values(&[METRICS.topic_label(topic)])
+                .observe(sent_at.elapsed().as_secs_f64());
+        }
+        if let Err(e) = &receipt {
+            if is_fatal(e) {
+                // Only the producer of this topic has to be rebuilt.
+                if let Some((current, pulsar_client)) = self.client.lock().await.as_mut() {
+                    if *current == generation {
+                        pulsar_client.discard_producer(topic);
+                    }
+                }
+            }
+        }
+        receipt.map_err(SendError::Pulsar)
+    }
+
+    /// Create the producer of a topic if the client has none yet, together
+    /// with the generation of the client.
+    ///
+    /// The client is not held while the broker creates the producer, so the
+    /// sends to the other topics are not held up meanwhile.
+    async fn create_producer(
+        &self,
+        topic: &str,
+    ) -> Result<Option<(u64, Producer<TokioExecutor>)>, SendError> {
+        let (generation, create_producer) = match self.client.lock().await.as_ref() {
+            Some((_, pulsar_client)) if pulsar_client.has_producer(topic) => return Ok(None),
+            Some((generation, pulsar_client)) => {
+                (*generation, pulsar_client.create_producer(topic))
+            }
+            None => return Err(SendError::NotConnected),
+        };
+        match create_producer.await {
+            Ok(producer) => {
+                info!("Created producer for topic '{}'.", topic);
+                Ok(Some((generation, producer)))
+            }
+            Err(e) => {
+                // Only this topic fails, unless the broker is unreachable.
+                if is_connection_lost(&e) {
+                    self.reconnect(generation).await;
+                } else {
+                    warn!("Could not create the producer of topic '{}': {}", topic, e);
+                }
+                Err(SendError::Pulsar(e))
+            }
+        }
+    }
+
+    /// Drop the client of a generation, because its connection to the broker
+    /// is lost, and have it rebuilt. A client that replaced it is kept.
+    async fn reconnect(&self, generation: u64) {
+        let mut client = self.client.lock().await;
+        if let Some((current, pulsar_client)) = client.as_ref() {
+            if *current == generation {
+                error!("The Pulsar client is broken, reconnecting.");
+                METRICS
+                    .producers_active
+                    .sub(pulsar_client.producers.len() as i64);
+                *client = None;
+                self.broken.notify_one();
+            }
+        }
+    }
+
+    /// Periodically close the producers that have been idle for `idle_timeout`.
+    ///
+    /// This never returns and is meant to be spawned.
+    pub async fn close_idle_producers(self: Arc<Self>, idle_timeout: Duration) {
+        loop {
+            sleep(idle_timeout / 2).await;
+            let idle_producers = match self.client.lock().await.as_mut() {
+                Some((_, pulsar_client)) => pulsar_client.take_idle_producers(idle_timeout),
+                None => Vec::new(),
+            };
+            // Closed after releasing the client, so the sends are not held up.
+            for (topic, mut producer) in idle_producers {
+                match producer.close().await {
+                    Ok(()) => info!("Closed the idle producer of topic '{}'.", topic),
+                    Err(e) => warn!(
+                        "Could not cleanly close the idle producer of topic '{}': {}",
+                        topic, e
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Take the client out and close all of its producers.
+    pub async fn close(&self) -> Vec<(String, PulsarError)> {
+        match self.client.lock().await.take() {
+            Some((_, mut pulsar_client)) => pulsar_client.close().await,
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloud_event::tests::conformance_errors;
+    use serde_json::json;
+
+    fn message(trace_context: HashMap<String, String>, mode: ContentMode) -> Message {
+        Message {
+            data: String::from("<premis:event/>"),
+            id: String::from("111"),
+            event_type: String::from("be.mediahaven.flow.archived"),
+            source: String::from("urn:meemoo:mh-events2pulsar"),
+            event_time: "2019-03-30T05:28:40Z".parse().unwrap(),
+            subject: String::from("a1"),
+            correlation_id: String::from("webhook-42"),
+            outcome: String::from("NOK"),
+            trace_context,
+            mode,
+            event: None,
+            event_only: false,
+        }
+    }
+
+    #[test]
+    fn test_serialize_message() {
+        // Act
+        let message =
+            Message::serialize_message(message(HashMap::new(), ContentMode::Structured)).unwrap();
+        // Assert
+        assert_eq!(message.properties["correlation_id"], "webhook-42");
+        assert_eq!(message.properties["id"], "111");
+        let payload: serde_json::Value = serde_json::from_slice(&message.payload).unwrap();
+        assert_eq!(conformance_errors(&payload), Vec::<String>::new());
+        assert_eq!(payload["id"], "111");
+        assert_eq!(payload["type"], "be.mediahaven.flow.archived");
+        assert_eq!(payload["source"], "urn:meemoo:mh-events2pulsar");
+        assert_eq!(payload["time"], "2019-03-30T05:28:40Z");
+        assert_eq!(payload["correlationid"], "webhook-42");
+        assert_eq!(payload["outcome"], "NOK");
+        assert_eq!(message.properties["outcome"], "NOK");
+        assert_eq!(payload["data"]["premis"], "<premis:event/>");
+    }
+
+    #[test]
+    fn test_serialize_message_trace_context() {
+        // Arrange
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let trace_context = HashMap::from([(String::from("traceparent"), traceparent.to_string())]);
+        // Act
+        let message =
+            Message::serialize_message(message(trace_context, ContentMode::Structured)).unwrap();
+        // Assert
+        let payload: serde_json::Value = serde_json::from_slice(&message.payload).unwrap();
+        assert_eq!(conformance_errors(&payload), Vec::<String>::new());
+        assert_eq!(payload["traceparent"], traceparent);
+        assert_eq!(message.properties["traceparent"], traceparent);
+    }
+
+    #[test]
+    fn test_serialize_message_event() {
+        // Arrange
+        let mut with_event = message(HashMap::new(), ContentMode::Structured);
+        with_event.event = Some(json!({ "eventType": "FLOW.ARCHIVED" }));
+        let mut event_only = message(HashMap::new(), ContentMode::Structured);
+        event_only.event = Some(json!({ "eventType": "FLOW.ARCHIVED" }));
+        event_only.event_only = true;
+        // Act
+        let with_event = Message::serialize_message(with_event).unwrap();
+        let event_only = Message::serialize_message(event_only).unwrap();
+        // Assert
+        let payload: serde_json::Value = serde_json::from_slice(&with_event.payload).unwrap();
+        assert_eq!(conformance_errors(&payload), Vec::<String>::new());
+        assert_eq!(payload["data"]["premis"], "<premis:event/>");
+        assert_eq!(payload["data"]["event"]["eventType"], "FLOW.ARCHIVED");
+        let payload: serde_json::Value = serde_json::from_slice(&event_only.payload).unwrap();
+        assert_eq!(
+            payload["data"],
+            json!({ "event": { "eventType": "FLOW.ARCHIVED" } })
+        );
+    }
+
+    #[test]
+    fn test_serialize_rdf_message() {
+        // Act
+        let message = RdfMessage::serialize_message(RdfMessage {
+            data: String::from(
+                "<urn:mediahaven:event:111> a <http://www.loc.gov/premis/rdf/v3/Event> .",
+            ),
+            id: String::from("111"),
+            event_time: "2019-03-30T05:28:40Z".parse().unwrap(),
+            correlation_id: String::from("webhook-42"),
+            trace_context: HashMap::new(),
+            format: RdfFormat::NTriples,
+        })
+        .unwrap();
+        // Assert
+        assert_eq!(
+            message.properties["content_type"],
+            "application/n-triples; charset=utf-8"
+        );
+        assert_eq!(message.properties["correlation_id"], "webhook-42");
+        assert_eq!(message.event_time, Some(1553923720000));
+        assert_eq!(
+            rdf_topic("be.mediahaven.flow.archived"),
+            "be.mediahaven.flow.archived.rdf"
+        );
+    }
+
+    #[test]
+    fn test_is_connection_lost() {
+        // Arrange
+        let disconnected = PulsarError::Connection(ConnectionError::Disconnected);
+        let topic_not_found = PulsarError::Connection(ConnectionError::PulsarError(
+            Some(pulsar::proto::ServerError::TopicNotFound),
+            None,
+        ));
+        let fenced = PulsarError::Producer(ProducerError::Fenced);
+        // Assert
+        assert!(is_connection_lost(&disconnected));
+        assert!(!is_connection_lost(&topic_not_found));
+        assert!(!is_connection_lost(&fenced));
+        assert!(is_fatal(&fenced));
+    }
+
+    #[test]
+    fn test_serialize_message_binary() {
+        // Act
+        let message =
+            Message::serialize_message(message(HashMap::new(), ContentMode::Binary)).unwrap();
+        // Assert
+        assert_eq!(message.payload, b"<premis:event/>");
+        assert_eq!(
+            message.properties["content_type"],
+            "application/xml; charset=utf-8"
+        );
+        let attributes: serde_json::Map<String, serde_json::Value> = message
+            .properties
+            .iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix("ce_")?.to_string(), json!(value))))
+            .collect();
+        assert_eq!(
+            conformance_errors(&serde_json::Value::Object(attributes)),
+            Vec::<String>::new()
+        );
+        assert_eq!(message.properties["ce_id"], "111");
+        assert_eq!(message.properties["ce_type"], "be.mediahaven.flow.archived");
+        assert_eq!(message.properties["ce_time"], "2019-03-30T05:28:40Z");
+        assert_eq!(message.properties["ce_datacontenttype"], "application/xml");
+        assert_eq!(message.properties["ce_correlationid"], "webhook-42");
+        assert_eq!(message.properties["ce_outcome"], "NOK");
+    }
+
+    #[test]
+    fn test_producers_least_recently_used() {
+        // Arrange
+        let mut producers = Producers::new(2);
+        assert!(producers.insert("a", 1).is_none());
+        assert!(producers.insert("b", 2).is_none());
+        producers.producers.get_mut("a").unwrap().last_used -= Duration::from_secs(60);
+        producers.producers.get_mut("b").unwrap().last_used -= Duration::from_secs(30);
+        assert_eq!(producers.get_mut("a"), Some(&mut 1));
+        // Act
+        let evicted = producers.insert("c", 3);
+        // Assert
+        assert_eq!(evicted, Some((String::from("b"), 2)));
+        assert_eq!(producers.len(), 2);
+        assert!(producers.contains("a"));
+        assert!(producers.contains("c"));
+    }
+
+    #[test]
+    fn test_producers_insert_existing() {
+        // Arrange
+        let mut producers = Producers::new(2);
+        producers.insert("a", 1);
+        // Act
+        let evicted = producers.insert("a", 2);
+        // Assert
+        assert_eq!(evicted, Some((String::from("a"), 2)));
+        assert_eq!(producers.get_mut("a"), Some(&mut 1));
+    }
+
+    #[test]
+    fn test_producers_take_idle() {
+        // Arrange
+        let mut producers = Producers::new(3);
+        producers.insert("a", 1);
+        producers.insert("b", 2);
+        producers.insert("c", 3);
+        producers.producers.get_mut("a").unwrap().last_used -= Duration::from_secs(120);
+        producers.producers.get_mut("c").unwrap().last_used -= Duration::from_secs(90);
+        // Act
+        let mut idle = producers.take_idle(Duration::from_secs(60));
+        // Assert
+        idle.sort();
+        assert_eq!(idle, vec![(String::from("a"), 1), (String::from("c"), 3)]);
+        assert_eq!(producers.len(), 1);
+        assert!(producers.contains("b"));
     }
 }
@@ -0,0 +1,450 @@
+//! The premis event as RDF, using the PREMIS 3 OWL ontology.
+//!
+//! ```turtle
+//! <urn:mediahaven:event:111> a premis:Event ;
+//!     dct:type eventType:ing, "FLOW.ARCHIVED" ;
+//!     premis:identifier [ a premis:Identifier ; dct:type "MEDIAHAVEN_EVENT" ; rdf:value "111" ] ;
+//!     prov:startedAtTime "2019-03-30T05:28:40Z"^^xsd:dateTime ;
+//!     premis:outcome "OK" ;
+//!     prov:wasAssociatedWith [ a premis:Agent ; premis:identifier [ ... ] ] ;
+//!     prov:used [ a premis:Object ; premis:identifier [ ... ] ] .
+//! ```
+
+use chrono::SecondsFormat;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::Event;
+
+const PREMIS: &str = "http://www.loc.gov/premis/rdf/v3/";
+const EVENT_TYPE: &str = "http://id.loc.gov/vocabulary/preservation/eventType/";
+const PROV: &str = "http://www.w3.org/ns/prov#";
+const DCT: &str = "http://purl.org/dc/terms/";
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+const PREFIXES: [(&str, &str); 6] = [
+    ("premis", PREMIS),
+    ("eventType", EVENT_TYPE),
+    ("prov", PROV),
+    ("dct", DCT),
+    ("rdf", RDF),
+    ("xsd", XSD),
+];
+
+/// The MediaHaven event types that have an equivalent in the EventType
+/// vocabulary of the Library of Congress.
+const EVENT_TYPES: [(&str, &str); 5] = [
+    ("FLOW.ARCHIVED", "ing"),
+    ("RECORDS.CREATE", "cre"),
+    ("RECORDS.UPDATE", "mem"),
+    ("RECORDS.DELETE", "del"),
+    ("FLOW.DELETED", "del"),
+];
+
+/// How an event is serialized to RDF.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RdfFormat {
+    JsonLd,
+    Turtle,
+    NTriples,
+}
+
+impl RdfFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            RdfFormat::JsonLd => "application/ld+json",
+            RdfFormat::Turtle => "text/turtle",
+            RdfFormat::NTriples => "application/n-triples",
+        }
+    }
+}
+
+enum Term {
+    Iri(String),
+    Literal(String),
+    /// A literal with an XSD datatype.
+    Typed(String, &'static str),
+    Node(Node),
+}
+
+/// A resource with its type and properties, a blank node without an IRI.
+struct Node {
+    iri: Option<String>,
+    class: String,
+    properties: Vec<(String, Term)>,
+}
+
+fn identifier(identifier_type: &str, value: &str) -> Term {
+    Term::Node(Node {
+        iri: None,
+        class: format!("{}Identifier", PREMIS),
+        properties: vec![
+            (
+                format!("{}type", DCT),
+                Term::Literal(identifier_type.to_string()),
+            ),
+            (format!("{}value", RDF), Term::Literal(value.to_string())),
+        ],
+    })
+}
+
+fn linked(class: &str, identifier_type: &str, value: &str) -> Term {
+    Term::Node(Node {
+        iri: None,
+        class: format!("{}{}", PREMIS, class),
+        properties: vec![(
+            format!("{}identifier", PREMIS),
+            identifier(identifier_type, value),
+        )],
+    })
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The prefixed name of an IRI, if it is in one of the known namespaces.
+fn compact(iri: &str) -> Option<String> {
+    PREFIXES.iter().find_map(|(prefix, namespace)| {
+        let local = iri.strip_prefix(namespace)?;
+        let valid = !local.is_empty()
+            && local
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        valid.then(|| format!("{}:{}", prefix, local))
+    })
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Node {
+    fn event(event: &Event) -> Node {
+        let mut properties = Vec::new();
+        if let Some((_, code)) = EVENT_TYPES
+            .iter()
+            .find(|(event_type, _)| *event_type == event.event_type)
+        {
+            properties.push((
+                format!("{}type", DCT),
+                Term::Iri(format!("{}{}", EVENT_TYPE, code)),
+            ));
+        }
+        // The MediaHaven event type itself, as not all of them have an
+        // equivalent.
+        properties.push((
+            format!("{}type", DCT),
+            Term::Literal(event.event_type.clone()),
+        ));
+        properties.push((
+            format!("{}identifier", PREMIS),
+            identifier(
+                &event.event_identifier.event_identifier_type,
+                &event.event_identifier.event_identifier_value,
+            ),
+        ));
+        properties.push((
+            format!("{}startedAtTime", PROV),
+            Term::Typed(
+                event
+                    .event_timestamp
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                "dateTime",
+            ),
+        ));
+        if let Some(event_detail) = &event.event_detail {
+            properties.push((
+                format!("{}note", PREMIS),
+                Term::Literal(event_detail.clone()),
+            ));
+        }
+        for outcome in event.outcomes() {
+            properties.push((
+                format!("{}outcome", PREMIS),
+                Term::Literal(outcome.to_string()),
+            ));
+        }
+        for agent in &event.linking_agent_identifier {
+            properties.push((
+                format!("{}wasAssociatedWith", PROV),
+                linked(
+                    "Agent",
+                    &agent.linking_agent_identifier_type,
+                    &agent.linking_agent_identifier_value,
+                ),
+            ));
+        }
+        for object in &event.linking_object_identifier {
+            properties.push((
+                format!("{}used", PROV),
+                linked(
+                    "Object",
+                    &object.linking_object_identifier_type,
+                    &object.linking_object_identifier_value,
+                ),
+            ));
+        }
+        Node {
+            iri: Some(event.iri()),
+            class: format!("{}Event", PREMIS),
+            properties,
+        }
+    }
+
+    fn to_json_ld(&self) -> Value {
+        let compact_or_full = |iri: &str| compact(iri).unwrap_or_else(|| iri.to_string());
+        let mut object = Map::new();
+        if let Some(iri) = &self.iri {
+            object.insert(String::from("@id"), json!(iri));
+        }
+        object.insert(String::from("@type"), json!(compact_or_full(&self.class)));
+        for (predicate, term) in &self.properties {
+            let value = match term {
+                Term::Iri(iri) => json!({ "@id": compact_or_full(iri) }),
+                Term::Literal(value) => json!(value),
+                Term::Typed(value, datatype) => {
+                    json!({ "@value": value, "@type": format!("xsd:{}", datatype) })
+                }
+                Term::Node(node) => node.to_json_ld(),
+            };
+            // A predicate with more than one value becomes an array.
+            match object.get_mut(&compact_or_full(predicate)) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(previous) => *previous = json!([previous.take(), value]),
+                None => {
+                    object.insert(compact_or_full(predicate), value);
+                }
+            }
+        }
+        Value::Object(object)
+    }
+
+    fn to_turtle(&self, indent: usize) -> String {
+        let iri_to_turtle = |iri: &str| compact(iri).unwrap_or_else(|| format!("<{}>", iri));
+        let term_to_turtle = |term: &Term| match term {
+            Term::Iri(iri) => iri_to_turtle(iri),
+            Term::Literal(value) => format!("\"{}\"", escape(value)),
+            Term::Typed(value, datatype) => format!("\"{}\"^^xsd:{}", escape(value), datatype),
+            Term::Node(node) => node.to_turtle(indent + 1),
+        };
+        let padding = "    ".repeat(indent + 1);
+        let mut statements = vec![format!("a {}", iri_to_turtle(&self.class))];
+        for (predicate, term) in &self.properties {
+            statements.push(format!(
+                "{} {}",
+                iri_to_turtle(predicate),
+                term_to_turtle(term)
+            ));
+        }
+        let statements = statements.join(&format!(" ;\n{}", padding));
+        match &self.iri {
+            Some(iri) => format!("<{}> {} .\n", iri, statements),
+            None => format!("[\n{}{}\n{}]", padding, statements, "    ".repeat(indent)),
+        }
+    }
+
+    /// Write the triples, returning the subject. The blank nodes are
+    /// numbered in the order they are written.
+    fn to_ntriples(&self, triples: &mut Vec<String>, blank_nodes: &mut usize) -> String {
+        let subject = match &self.iri {
+            Some(iri) => format!("<{}>", iri),
+            None => {
+                *blank_nodes += 1;
+                format!("_:b{}", blank_nodes)
+            }
+        };
+        triples.push(format!("{} <{}type> <{}> .", subject, RDF, self.class));
+        for (predicate, term) in &self.properties {
+            let object = match term {
+                Term::Iri(iri) => format!("<{}>", iri),
+                Term::Literal(value) => format!("\"{}\"", escape(value)),
+                Term::Typed(value, datatype) => {
+                    format!("\"{}\"^^<{}{}>", escape(value), XSD, datatype)
+                }
+                Term::Node(node) => node.to_ntriples(triples, blank_nodes),
+            };
+            triples.push(format!("{} <{}> {} .", subject, predicate, object));
+        }
+        subject
+    }
+}
+
+impl Event {
+    /// The IRI of the event, based on its identifier.
+    pub fn iri(&self) -> String {
+        format!(
+            "urn:mediahaven:event:{}",
+            encode(&self.event_identifier.event_identifier_value)
+        )
+    }
+
+    /// The event as a JSON-LD document, compacted with the prefixes of the
+    /// ontologies in its `@context`.
+    pub fn to_json_ld(&self) -> Value {
+        let mut document = Node::event(self).to_json_ld();
+        let context: Map<String, Value> = PREFIXES
+            .iter()
+            .map(|(prefix, namespace)| (prefix.to_string(), json!(namespace)))
+            .collect();
+        document
+            .as_object_mut()
+            .expect("A node is a JSON object")
+            .insert(String::from("@context"), Value::Object(context));
+        document
+    }
+
+    pub fn to_rdf(&self, format: RdfFormat) -> String {
+        let node = Node::event(self);
+        match format {
+            RdfFormat::JsonLd => self.to_json_ld().to_string(),
+            RdfFormat::Turtle => {
+                let prefixes: String = PREFIXES
+                    .iter()
+                    .map(|(prefix, namespace)| format!("@prefix {}: <{}> .\n", prefix, namespace))
+                    .collect();
+                format!("{}\n{}", prefixes, node.to_turtle(0))
+            }
+            RdfFormat::NTriples => {
+                let mut triples = Vec::new();
+                node.to_ntriples(&mut triples, &mut 0);
+                triples
+                    .iter()
+                    .map(|triple| format!("{}\n", triple))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREMIS_EVENT: &str = r##"<premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+        <premis:eventIdentifier>
+            <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+            <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+        </premis:eventIdentifier>
+        <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+        <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+        <premis:eventDetail>Ingested "a1"</premis:eventDetail>
+        <premis:eventOutcomeInformation>
+            <premis:eventOutcome>OK</premis:eventOutcome>
+        </premis:eventOutcomeInformation>
+        <premis:linkingAgentIdentifier>
+            <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+            <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+        </premis:linkingAgentIdentifier>
+        <premis:linkingObjectIdentifier>
+            <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+            <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+        </premis:linkingObjectIdentifier>
+    </premis:event>"##;
+
+    #[test]
+    fn test_to_json_ld() {
+        // Act
+        let document = Event::new(PREMIS_EVENT).to_json_ld();
+        // Assert
+        assert_eq!(document["@context"]["premis"], PREMIS);
+        assert_eq!(document["@id"], "urn:mediahaven:event:111");
+        assert_eq!(document["@type"], "premis:Event");
+        assert_eq!(
+            document["dct:type"],
+            json!([{ "@id": "eventType:ing" }, "FLOW.ARCHIVED"])
+        );
+        assert_eq!(
+            document["prov:startedAtTime"],
+            json!({ "@value": "2019-03-30T05:28:40Z", "@type": "xsd:dateTime" })
+        );
+        assert_eq!(
+            document["prov:used"],
+            json!({
+                "@type": "premis:Object",
+                "premis:identifier": {
+                    "@type": "premis:Identifier",
+                    "dct:type": "EXTERNAL_ID",
+                    "rdf:value": "a1",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_ntriples() {
+        // Act
+        let ntriples = Event::new(PREMIS_EVENT).to_rdf(RdfFormat::NTriples);
+        // Assert
+        let triples: Vec<&str> = ntriples.lines().collect();
+        assert_eq!(triples.len(), 22);
+        assert!(triples.iter().all(|triple| triple.ends_with(" .")));
+        assert!(triples.contains(
+            &"<urn:mediahaven:event:111> <http://purl.org/dc/terms/type> <http://id.loc.gov/vocabulary/preservation/eventType/ing> ."
+        ));
+        assert!(triples.contains(
+            &r#"<urn:mediahaven:event:111> <http://www.loc.gov/premis/rdf/v3/note> "Ingested \"a1\"" ."#
+        ));
+        assert!(
+            triples.contains(&r#"_:b5 <http://www.w3.org/1999/02/22-rdf-syntax-ns#value> "a1" ."#)
+        );
+    }
+
+    #[test]
+    fn test_to_turtle() {
+        // Act
+        let turtle = Event::new(PREMIS_EVENT).to_rdf(RdfFormat::Turtle);
+        // Assert
+        assert!(turtle.starts_with("@prefix premis: <http://www.loc.gov/premis/rdf/v3/> .\n"));
+        assert!(turtle.contains("<urn:mediahaven:event:111> a premis:Event ;\n"));
+        assert!(
+            turtle.contains("    prov:startedAtTime \"2019-03-30T05:28:40Z\"^^xsd:dateTime ;\n")
+        );
+        assert!(turtle.ends_with("    ] .\n"));
+    }
+
+    #[test]
+    fn test_iri() {
+        let event = Event::from_json(json!({
+            "eventIdentifier": {
+                "eventIdentifierType": "MEDIAHAVEN_EVENT",
+                "eventIdentifierValue": "a b/c",
+            },
+            "eventType": "RECORDS.UPDATE",
+            "eventDateTime": "2019-03-30T05:28:40Z",
+            "eventOutcomeInformation": { "eventOutcome": "OK" },
+            "linkingAgentIdentifier": [{
+                "linkingAgentIdentifierType": "MEDIAHAVEN_USER",
+                "linkingAgentIdentifierValue": "u1",
+            }],
+            "linkingObjectIdentifier": [{
+                "linkingObjectIdentifierType": "EXTERNAL_ID",
+                "linkingObjectIdentifierValue": "a1",
+            }],
+        }))
+        .unwrap();
+        assert_eq!(event.iri(), "urn:mediahaven:event:a%20b%2Fc");
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// How long to wait before publishing spooled events again, doubling from
+/// `min` up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff { min, max }
+    }
+
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    /// The wait after `current`.
+    pub fn next(&self, current: Duration) -> Duration {
+        (current * 2).clamp(self.min, self.max.max(self.min))
+    }
+}
+
+/// A premis event in the spool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spooled {
+    pub path: PathBuf,
+    pub topic: String,
+    /// The request id and index of an event of an accepted request.
+    pub accepted: Option<(String, usize)>,
+}
+
+/// A directory on disk where premis events are kept when they could not be
+/// sent to Pulsar, so they can be replayed later on.
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    pub fn new(dir: &str) -> io::Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        Ok(Spool { dir })
+    }
+
+    /// Write a premis event destined for `topic` to the spool.
+    ///
+    /// Every event gets its own file: `{topic}.{uuid}.xml`.
+    pub fn write(&self, topic: &str, premis_event_xml: &str) -> io::Result<PathBuf> {
+        let path = self
+            .dir
+            .join(format!("{}.{}.xml", topic, Uuid::new_v4().to_simple()));
+        fs::write(&path, premis_event_xml)?;
+        Ok(path)
+    }
+
+    /// Write the premis event at `index` of an accepted request to the spool.
+    ///
+    /// The file, `{topic}.{request_id}-{index}.xml`, is removed again once
+    /// the event is published.
+    pub fn write_accepted(
+        &self,
+        topic: &str,
+        request_id: &str,
+        index: usize,
+        premis_event_xml: &str,
+    ) -> io::Result<PathBuf> {
+        let path = self.accepted_path(topic, request_id, index);
+        fs::write(&path, premis_event_xml)?;
+        Ok(path)
+    }
+
+    /// Remove an event of an accepted request once it has been published.
+    pub fn remove_accepted(&self, topic: &str, request_id: &str, index: usize) -> io::Result<()> {
+        fs::remove_file(self.accepted_path(topic, request_id, index))
+    }
+
+    fn accepted_path(&self, topic: &str, request_id: &str, index: usize) -> PathBuf {
+        self.dir
+            .join(format!("{}.{}-{}.xml", topic, request_id, index))
+    }
+
+    /// The files of the events of an accepted request that are still in the
+    /// spool, with their index in the request and in that order.
+    pub fn accepted(&self, request_id: &str) -> io::Result<Vec<(usize, PathBuf)>> {
+        let mut accepted: Vec<(usize, PathBuf)> = self
+            .spooled()?
+            .into_iter()
+            .filter_map(|spooled| match spooled.accepted {
+                Some((id, index)) if id == request_id => Some((index, spooled.path)),
+                _ => None,
+            })
+            .collect();
+        accepted.sort();
+        Ok(accepted)
+    }
+
+    /// All the events in the spool, the oldest first.
+    pub fn spooled(&self) -> io::Result<Vec<Spooled>> {
+        let mut spooled = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let parsed = path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .and_then(|file_name| file_name.strip_suffix(".xml"))
+                .and_then(|file_name| file_name.rsplit_once('.'))
+                .map(|(topic, name)| {
+                    // Unsent events are named by a UUID without hyphens.
+                    let accepted = name.rsplit_once('-').and_then(|(request_id, index)| {
+                        Some((request_id.to_string(), index.parse::<usize>().ok()?))
+                    });
+                    (topic.to_string(), accepted)
+                });
+            if let Some((topic, accepted)) = parsed {
+                let modified = entry.metadata()?.modified()?;
+                spooled.push((
+                    modified,
+                    Spooled {
+                        path,
+                        topic,
+                        accepted,
+                    },
+                ));
+            }
+        }
+        spooled.sort_by(|(a, a_spooled), (b, b_spooled)| {
+            a.cmp(b).then_with(|| a_spooled.path.cmp(&b_spooled.path))
+        });
+        Ok(spooled.into_iter().map(|(_, spooled)| spooled).collect())
+    }
+
+    /// Set a file aside that can not be replayed, as `{file}.invalid`.
+    pub fn set_aside(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut invalid = path.as_os_str().to_owned();
+        invalid.push(".invalid");
+        fs::rename(path, &invalid)?;
+        Ok(PathBuf::from(invalid))
+    }
+
+    /// Check that events can be written to the spool.
+    pub fn check(&self) -> io::Result<()> {
+        let path = self
+            .dir
+            .join(format!(".check.{}", Uuid::new_v4().to_simple()));
+        fs::write(&path, "")?;
+        fs::remove_file(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let spool = Spool::new(dir.to_str().unwrap()).unwrap();
+        // Act
+        let path = spool
+            .write("be.mediahaven.flow.archived", "<premis:event/>")
+            .unwrap();
+        // Assert
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("be.mediahaven.flow.archived."));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<premis:event/>");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_accepted() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let spool = Spool::new(dir.to_str().unwrap()).unwrap();
+        spool
+            .write_accepted("be.mediahaven.flow.archived", "abc", 1, "<premis:event/>")
+            .unwrap();
+        spool
+            .write_accepted("be.mediahaven.flow.archived", "def", 0, "<premis:event/>")
+            .unwrap();
+        spool
+            .write("be.mediahaven.flow.archived", "<premis:event/>")
+            .unwrap();
+        // Act
+        let accepted = spool.accepted("abc").unwrap();
+        // Assert
+        assert_eq!(
+            accepted,
+            vec![(1, dir.join("be.mediahaven.flow.archived.abc-1.xml"))]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(300));
+        assert_eq!(backoff.min(), Duration::from_millis(100));
+        assert_eq!(
+            backoff.next(Duration::from_millis(100)),
+            Duration::from_millis(200)
+        );
+        assert_eq!(
+            backoff.next(Duration::from_millis(200)),
+            Duration::from_millis(300)
+        );
+    }
+
+    #[test]
+    fn test_spooled() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        let spool = Spool::new(dir.to_str().unwrap()).unwrap();
+        let unsent = spool
+            .write("be.mediahaven.flow.archived", "<premis:event/>")
+            .unwrap();
+        let request_id = Uuid::new_v4().to_string();
+        let accepted = spool
+            .write_accepted(
+                "be.mediahaven.records.update",
+                &request_id,
+                2,
+                "<premis:event/>",
+            )
+            .unwrap();
+        let invalid = spool
+            .write("be.mediahaven.flow.archived", "<premis:event")
+            .unwrap();
+        spool.set_aside(&invalid).unwrap();
+        // Act
+        let mut spooled = spool.spooled().unwrap();
+        spooled.sort_by(|a, b| a.topic.cmp(&b.topic));
+        // Assert
+        assert_eq!(
+            spooled,
+            vec![
+                Spooled {
+                    path: unsent,
+                    topic: String::from("be.mediahaven.flow.archived"),
+                    accepted: None,
+                },
+                Spooled {
+                    path: accepted,
+                    topic: String::from("be.mediahaven.records.update"),
+                    accepted: Some((request_id, 2)),
+                },
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
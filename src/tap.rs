@@ -0,0 +1,239 @@
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use actix_web::{rt::time::timeout, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::event_result::{EventResult, Outcome};
+use mh_events2pulsar::Event;
+
+/// Events a subscriber can fall behind before it misses some.
+const BUFFER_SIZE: usize = 1024;
+/// Comments are sent this often on an idle stream, so proxies keep it open
+/// and a closed connection is noticed.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// The live tap of the bridge, streamed on `/admin/tap`.
+pub static TAP: LazyLock<Tap> = LazyLock::new(|| Tap::new(BUFFER_SIZE));
+
+/// A premis event and what happened to it, as shown on the tap.
+#[derive(Serialize, Debug, Clone)]
+pub struct TapEvent {
+    pub processed_at: DateTime<Utc>,
+    pub correlation_id: String,
+    pub identifier: String,
+    pub event_type: String,
+    pub event_time: DateTime<Utc>,
+    pub subject: String,
+    pub agents: Vec<String>,
+    pub topic: String,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+/// Only the events matching all of the given fields are streamed.
+#[derive(Deserialize, Debug, Default)]
+pub struct TapFilter {
+    pub event_type: Option<String>,
+    pub subject: Option<String>,
+    /// `published`, `duplicate` or `failed`.
+    pub outcome: Option<String>,
+    /// One of the linking agent identifiers.
+    pub agent: Option<String>,
+}
+
+impl TapFilter {
+    fn matches(&self, tap_event: &TapEvent) -> bool {
+        self.event_type
+            .as_ref()
+            .is_none_or(|event_type| event_type.eq_ignore_ascii_case(&tap_event.event_type))
+            && self
+                .subject
+                .as_ref()
+                .is_none_or(|subject| *subject == tap_event.subject)
+            && self
+                .outcome
+                .as_ref()
+                .is_none_or(|outcome| outcome == tap_event.outcome.label())
+            && self
+                .agent
+                .as_ref()
+                .is_none_or(|agent| tap_event.agents.contains(agent))
+    }
+}
+
+/// Broadcasts the processed events to the operators watching the tap.
+///
+/// Publishing never waits for the subscribers: one that falls more than the
+/// buffer behind skips the oldest events instead.
+pub struct Tap {
+    sender: broadcast::Sender<Arc<TapEvent>>,
+}
+
+impl Tap {
+    fn new(buffer_size: usize) -> Self {
+        Tap {
+            sender: broadcast::channel(buffer_size).0,
+        }
+    }
+
+    pub fn publish(&self, premis_event: &Event, correlation_id: &str, result: &EventResult) {
+        // Nobody is watching.
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let tap_event = TapEvent {
+            processed_at: Utc::now(),
+            correlation_id: correlation_id.to_string(),
+            identifier: result.identifier.clone(),
+            event_type: result.event_type.clone(),
+            event_time: premis_event.event_timestamp,
+            subject: premis_event.subject(),
+            agents: premis_event
+                .agents()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            topic: result.topic.clone(),
+            outcome: result.outcome.clone(),
+        };
+        // The subscribers can be gone in the meantime.
+        let _ = self.sender.send(Arc::new(tap_event));
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<TapEvent>> {
+        self.sender.subscribe()
+    }
+}
+
+fn server_sent_event(event: &str, data: &serde_json::Value) -> web::Bytes {
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// Stream the processed events as Server-Sent Events.
+///
+/// The stream sends `event` events with a [`TapEvent`], and `lagged` events
+/// with the number of events that were skipped because the client could not
+/// keep up.
+pub async fn tap(filter: web::Query<TapFilter>) -> HttpResponse {
+    let receiver = TAP.subscribe();
+    let events = stream::unfold(
+        (receiver, filter.into_inner()),
+        |(mut receiver, filter)| async move {
+            loop {
+                let bytes = match timeout(KEEP_ALIVE, receiver.recv()).await {
+                    Err(_) => web::Bytes::from_static(b": keep-alive\n\n"),
+                    Ok(Ok(tap_event)) if filter.matches(&tap_event) => {
+                        server_sent_event("event", &json!(tap_event.as_ref()))
+                    }
+                    Ok(Ok(_)) => continue,
+                    Ok(Err(RecvError::Lagged(skipped))) => {
+                        server_sent_event("lagged", &json!({ "skipped": skipped }))
+                    }
+                    Ok(Err(RecvError::Closed)) => return None,
+                };
+                return Some((Ok::<_, actix_web::Error>(bytes), (receiver, filter)));
+            }
+        },
+    );
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREMIS_EVENT: &str = r##"<premis:event xmlns:premis="info:lc/xmlns/premis-v2">
+        <premis:eventIdentifier>
+            <premis:eventIdentifierType>MEDIAHAVEN_EVENT</premis:eventIdentifierType>
+            <premis:eventIdentifierValue>111</premis:eventIdentifierValue>
+        </premis:eventIdentifier>
+        <premis:eventType>FLOW.ARCHIVED</premis:eventType>
+        <premis:eventDateTime>2019-03-30T05:28:40Z</premis:eventDateTime>
+        <premis:eventOutcomeInformation>
+            <premis:eventOutcome>OK</premis:eventOutcome>
+        </premis:eventOutcomeInformation>
+        <premis:linkingAgentIdentifier>
+            <premis:linkingAgentIdentifierType>MEDIAHAVEN_USER</premis:linkingAgentIdentifierType>
+            <premis:linkingAgentIdentifierValue>703a53d2-dc66-4eb2-ab7f-73d5fd228852</premis:linkingAgentIdentifierValue>
+        </premis:linkingAgentIdentifier>
+        <premis:linkingObjectIdentifier>
+            <premis:linkingObjectIdentifierType>EXTERNAL_ID</premis:linkingObjectIdentifierType>
+            <premis:linkingObjectIdentifierValue>a1</premis:linkingObjectIdentifierValue>
+        </premis:linkingObjectIdentifier>
+    </premis:event>"##;
+
+    fn result(outcome: Outcome) -> EventResult {
+        EventResult {
+            identifier: String::from("111"),
+            event_type: String::from("FLOW.ARCHIVED"),
+            topic: String::from("be.mediahaven.flow.archived"),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_publish() {
+        // Arrange
+        let tap = Tap::new(1);
+        let premis_event = Event::new(PREMIS_EVENT);
+        tap.publish(&premis_event, "abc", &result(Outcome::Duplicate));
+        let mut receiver = tap.subscribe();
+        // Act
+        tap.publish(&premis_event, "abc", &result(Outcome::Duplicate));
+        tap.publish(
+            &premis_event,
+            "def",
+            &result(Outcome::Published { message_id: None }),
+        );
+        // Assert
+        assert_eq!(
+            receiver.try_recv().unwrap_err(),
+            broadcast::error::TryRecvError::Lagged(1)
+        );
+        let tap_event = receiver.try_recv().unwrap();
+        assert_eq!(tap_event.correlation_id, "def");
+        assert_eq!(tap_event.subject, "a1");
+        assert_eq!(
+            tap_event.agents,
+            vec!["703a53d2-dc66-4eb2-ab7f-73d5fd228852"]
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        // Arrange
+        let tap = Tap::new(1);
+        let mut receiver = tap.subscribe();
+        tap.publish(
+            &Event::new(PREMIS_EVENT),
+            "abc",
+            &result(Outcome::Failed {
+                reason: String::from("Not connected to Pulsar"),
+            }),
+        );
+        let tap_event = receiver.try_recv().unwrap();
+        // Act
+        let failed = TapFilter {
+            event_type: Some(String::from("flow.archived")),
+            outcome: Some(String::from("failed")),
+            agent: Some(String::from("703a53d2-dc66-4eb2-ab7f-73d5fd228852")),
+            ..Default::default()
+        };
+        let other_subject = TapFilter {
+            subject: Some(String::from("a2")),
+            ..Default::default()
+        };
+        // Assert
+        assert!(failed.matches(&tap_event));
+        assert!(!other_subject.matches(&tap_event));
+        assert!(TapFilter::default().matches(&tap_event));
+    }
+}
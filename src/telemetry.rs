@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::HeaderMap,
+    middleware::Next,
+    Error,
+};
+use chrono::{DateTime, Utc};
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    propagation::{Extractor, TextMapPropagator},
+    trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_sdk::{
+    error::{OTelSdkError, OTelSdkResult},
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+    Resource,
+};
+use serde_json::json;
+
+use mh_events2pulsar::{Config, TraceExporter};
+
+const SERVICE_NAME: &str = "mh-events2pulsar";
+
+/// Set up the tracer provider for the configured exporter.
+///
+/// The OTLP exporter is configured with the standard `OTEL_EXPORTER_OTLP_*`
+/// environment variables. The returned provider has to be shut down to flush
+/// the last spans.
+pub fn init(config: &Config) -> io::Result<Option<SdkTracerProvider>> {
+    let builder = SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build());
+    let provider = match config.trace_exporter {
+        TraceExporter::None => return Ok(None),
+        TraceExporter::Otlp => {
+            // The blocking HTTP client can not be created on the async runtime.
+            let exporter = thread::spawn(|| {
+                opentelemetry_otlp::SpanExporter::builder()
+                    .with_http()
+                    .build()
+            })
+            .join()
+            .expect("Building the OTLP exporter does not panic")
+            .map_err(|e| io::Error::other(e.to_string()))?;
+            builder.with_batch_exporter(exporter).build()
+        }
+        TraceExporter::Stdout => builder
+            .with_simple_exporter(opentelemetry_stdout::SpanExporter::default())
+            .build(),
+        TraceExporter::File => {
+            let path = config.trace_file.as_deref().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "TRACE_FILE is needed for the file exporter.",
+                )
+            })?;
+            builder
+                .with_simple_exporter(FileSpanExporter::new(path)?)
+                .build()
+        }
+    };
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer(SERVICE_NAME)
+}
+
+/// The W3C `traceparent` and `tracestate` of the span in `cx`, empty when
+/// there is no span.
+pub fn inject(cx: &Context) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(cx, &mut headers);
+    headers
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Middleware tracing the requests to `/events`, continuing the trace of the
+/// caller if it sent a `traceparent`. The probes and metrics are left out.
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if !req.path().starts_with("/events") {
+        return next.call(req).await;
+    }
+    let parent_cx = TraceContextPropagator::new().extract(&HeaderExtractor(req.headers()));
+    // Named after the route rather than the path, which holds identifiers.
+    let route = req.match_pattern();
+    let name = match &route {
+        Some(route) => format!("{} {}", req.method(), route),
+        None => req.method().to_string(),
+    };
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", req.method().to_string()),
+        KeyValue::new("url.path", req.path().to_string()),
+    ];
+    if let Some(route) = route {
+        attributes.push(KeyValue::new("http.route", route));
+    }
+    let span = tracer()
+        .span_builder(name)
+        .with_kind(SpanKind::Server)
+        .with_attributes(attributes)
+        .start_with_context(&tracer(), &parent_cx);
+    let cx = parent_cx.with_span(span);
+    let result = next.call(req).with_context(cx.clone()).await;
+    let span = cx.span();
+    match &result {
+        Ok(res) => {
+            span.set_attribute(KeyValue::new(
+                "http.response.status_code",
+                i64::from(res.status().as_u16()),
+            ));
+            if res.status().is_server_error() {
+                span.set_status(Status::error(res.status().to_string()));
+            }
+        }
+        Err(e) => {
+            let status = e.as_response_error().status_code();
+            span.set_attribute(KeyValue::new(
+                "http.response.status_code",
+                i64::from(status.as_u16()),
+            ));
+            if status.is_server_error() {
+                span.set_status(Status::error(e.to_string()));
+            }
+        }
+    }
+    span.end();
+    result
+}
+
+/// Writes every span as a line of JSON, for local testing.
+#[derive(Debug)]
+pub struct FileSpanExporter {
+    file: Mutex<File>,
+}
+
+impl FileSpanExporter {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSpanExporter {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339()
+}
+
+impl SpanExporter for FileSpanExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let mut file = self.file.lock().unwrap();
+        for span in batch {
+            let attributes: serde_json::Map<String, serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.to_string(), json!(kv.value.as_str())))
+                .collect();
+            let line = json!({
+                "trace_id": span.span_context.trace_id().to_string(),
+                "span_id": span.span_context.span_id().to_string(),
+                "parent_span_id": span.parent_span_id.to_string(),
+                "name": span.name,
+                "kind": format!("{:?}", span.span_kind),
+                "start_time": timestamp(span.start_time),
+                "end_time": timestamp(span.end_time),
+                "status": format!("{:?}", span.status),
+                "attributes": attributes,
+            });
+            writeln!(file, "{}", line).map_err(|e| OTelSdkError::InternalFailure(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.file
+            .lock()
+            .unwrap()
+            .flush()
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{Span, TracerProvider};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_inject() {
+        // Arrange
+        let provider = SdkTracerProvider::builder().build();
+        let span = provider.tracer("test").start("send");
+        let trace_id = span.span_context().trace_id();
+        let cx = Context::current_with_span(span);
+        // Act
+        let properties = inject(&cx);
+        // Assert
+        assert!(properties["traceparent"].starts_with(&format!("00-{}-", trace_id)));
+    }
+
+    #[test]
+    fn test_inject_without_span() {
+        assert!(inject(&Context::new()).is_empty());
+    }
+
+    #[test]
+    fn test_file_exporter() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("{}.jsonl", Uuid::new_v4().to_simple()));
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(FileSpanExporter::new(path.to_str().unwrap()).unwrap())
+            .build();
+        // Act
+        let mut span = provider.tracer("test").start("parse events");
+        span.set_attribute(KeyValue::new("events", 2));
+        span.end();
+        // Assert
+        let line: serde_json::Value =
+            serde_json::from_str(std::fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert_eq!(line["name"], "parse events");
+        assert_eq!(line["attributes"]["events"], "2");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use log::{error, info};
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    RootCertStore, ServerConfig,
+};
+
+use mh_events2pulsar::Config;
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Read a PEM certificate chain and private key.
+fn load_certified_key(
+    provider: &CryptoProvider,
+    cert_path: &str,
+    key_path: &str,
+) -> io::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!("No certificates in '{}'.", cert_path)));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| invalid_data(format!("No private key in '{}'.", key_path)))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(invalid_data)?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn modified(path: &str) -> io::Result<SystemTime> {
+    fs::metadata(path)?.modified()
+}
+
+/// Serves the certificate from `cert_path` and `key_path`, reloading it when
+/// the files change so a renewed certificate is picked up without a restart.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    provider: Arc<CryptoProvider>,
+    cert_path: String,
+    key_path: String,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(provider: Arc<CryptoProvider>, cert_path: &str, key_path: &str) -> io::Result<Self> {
+        let certified_key = load_certified_key(&provider, cert_path, key_path)?;
+        Ok(ReloadingCertResolver {
+            provider,
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            certified_key: RwLock::new(Arc::new(certified_key)),
+        })
+    }
+
+    pub fn reload(&self) -> io::Result<()> {
+        let certified_key = load_certified_key(&self.provider, &self.cert_path, &self.key_path)?;
+        *self.certified_key.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+
+    /// Check the modification times of the files every `interval` and reload
+    /// the certificate when they changed. A broken certificate is logged and
+    /// the previous one is kept.
+    pub fn watch(self: Arc<Self>, interval: Duration) {
+        thread::spawn(move || {
+            let modified_times = || -> io::Result<(SystemTime, SystemTime)> {
+                Ok((modified(&self.cert_path)?, modified(&self.key_path)?))
+            };
+            let mut last_modified = modified_times().ok();
+            loop {
+                thread::sleep(interval);
+                let current = modified_times().ok();
+                if current.is_none() || current == last_modified {
+                    continue;
+                }
+                match self.reload() {
+                    Ok(()) => {
+                        info!("Reloaded the TLS certificate '{}'.", self.cert_path);
+                        last_modified = current;
+                    }
+                    // Retried on the next check, the files might be half written.
+                    Err(e) => error!("Could not reload the TLS certificate: {}", e),
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.read().unwrap().clone())
+    }
+}
+
+/// The rustls server configuration, if TLS is configured.
+///
+/// With `tls_client_ca` set, clients have to present a certificate signed by
+/// one of the certificate authorities in that bundle.
+pub fn server_config(config: &Config) -> io::Result<Option<ServerConfig>> {
+    let (cert_path, key_path) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        (None, None) => return Ok(None),
+        _ => {
+            return Err(invalid_data(
+                "Both TLS_CERT and TLS_KEY are needed to enable TLS.",
+            ))
+        }
+    };
+    let provider = Arc::new(ring::default_provider());
+    let resolver = Arc::new(ReloadingCertResolver::new(
+        provider.clone(),
+        cert_path,
+        key_path,
+    )?);
+    if config.tls_reload_interval > 0 {
+        resolver
+            .clone()
+            .watch(Duration::from_secs(config.tls_reload_interval));
+    }
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?;
+    let builder = match &config.tls_client_ca {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(ca_path)?)) {
+                roots.add(cert?).map_err(invalid_data)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(invalid_data)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_cert_resolver(resolver);
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Some(server_config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn write_certificate(dir: &std::path::Path, name: &str) -> (String, String) {
+        let certified_key = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_path = dir.join("tls.crt");
+        let key_path = dir.join("tls.key");
+        fs::write(&cert_path, certified_key.cert.pem()).unwrap();
+        fs::write(&key_path, certified_key.key_pair.serialize_pem()).unwrap();
+        (
+            cert_path.to_str().unwrap().to_string(),
+            key_path.to_str().unwrap().to_string(),
+        )
+    }
+
+    #[test]
+    fn test_reload() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = write_certificate(&dir, "old.example.com");
+        let resolver =
+            ReloadingCertResolver::new(Arc::new(ring::default_provider()), &cert_path, &key_path)
+                .unwrap();
+        let old = resolver.certified_key.read().unwrap().cert[0].clone();
+        // Act
+        write_certificate(&dir, "new.example.com");
+        resolver.reload().unwrap();
+        // Assert
+        let new = resolver.certified_key.read().unwrap().cert[0].clone();
+        assert_ne!(old, new);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_keeps_certificate_on_error() {
+        // Arrange
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_simple().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = write_certificate(&dir, "example.com");
+        let resolver =
+            ReloadingCertResolver::new(Arc::new(ring::default_provider()), &cert_path, &key_path)
+                .unwrap();
+        let old = resolver.certified_key.read().unwrap().cert[0].clone();
+        // Act
+        fs::write(&cert_path, "garbage").unwrap();
+        let result = resolver.reload();
+        // Assert
+        assert!(result.is_err());
+        assert_eq!(resolver.certified_key.read().unwrap().cert[0], old);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}